@@ -3,10 +3,11 @@
 use crate::cgi_handler::*;
 use crate::config::RouteConfig;
 use crate::http_request::HttpRequest;
-use crate::http_response::HttpResponse;
+use crate::http_response::{ErrorPages, HttpResponse};
+use crate::path_resolver::{percent_encode_path, DocumentRoot};
+use crate::router::{expand_variables, RouteMatch};
+use crate::response_body::ResponseBody;
 use crate::session::SessionManager;
-use std::collections::HashMap;
-use std::path::Path;
 use std::time::Duration;
 use std::time::SystemTime;
 
@@ -16,7 +17,7 @@ fn handle_session(
     request: &HttpRequest,
 ) -> Result<String, HttpResponse> {
     // Do not check the session for these specific routes
-    let public_paths = vec!["/session", "/create-session"];
+    let public_paths = ["/session", "/create-session"];
     if public_paths.contains(&request.path.as_str()) {
         return Ok(String::new());
     }
@@ -37,8 +38,8 @@ fn handle_session(
                             "Set-Cookie".to_string(),
                             "session_id=; Max-Age=0".to_string(),
                         ),
-                    ],
-                    body: Vec::new(),
+                    ].into(),
+                    body: ResponseBody::empty(),
                 })
             }
         }
@@ -46,29 +47,39 @@ fn handle_session(
             // No session - Redirect
             Err(HttpResponse {
                 status_code: 302,
-                headers: vec![("Location".to_string(), "/session".to_string())],
-                body: Vec::new(),
+                headers: vec![("Location".to_string(), "/session".to_string())].into(),
+                body: ResponseBody::empty(),
             })
         }
     }
 }
 
-pub fn handle_route(
-    route: &RouteConfig,
-    request: HttpRequest,
-    error_page: Option<HashMap<u16, String>>,
-) -> HttpResponse {
+// Check the session of the request against the manager shared by every worker,
+// released before the handler runs
+fn check_session(request: &HttpRequest) -> Result<String, HttpResponse> {
+    let session_id = request.get_cookies().get("session_id").cloned();
     let mut session_manager = SessionManager::global()
         .lock()
         .expect("Failed to lock session manager");
+    handle_session(&mut session_manager, session_id, request)
+}
 
+pub fn handle_route(
+    route: &RouteConfig,
+    request: HttpRequest,
+    root: &DocumentRoot,
+    error_page: Option<ErrorPages>,
+) -> HttpResponse {
     // Get the session roads
     let session_routes = SessionManager::get_default_routes();
 
     // Check if it's a session route
     if let Some(session_route) = session_routes.get(&request.path) {
         if request.path == "/create-session" && request.method == "POST" {
-            let session_id = session_manager.create_session();
+            let session_id = SessionManager::global()
+                .lock()
+                .expect("Failed to lock session manager")
+                .create_session();
             return HttpResponse {
                 status_code: 302,
                 headers: vec![
@@ -77,64 +88,127 @@ pub fn handle_route(
                         "Set-Cookie".to_string(),
                         format!("session_id={}; Path=/", session_id),
                     ),
-                ],
-                body: Vec::new(),
+                ].into(),
+                body: ResponseBody::empty(),
             };
         } else {
             // For /session
-            return HttpResponse::page_server(
-                200,
-                session_route
-                    .default_file
-                    .as_deref()
-                    .unwrap_or("session.html"),
-                error_page,
-            );
+            let page = session_route
+                .default_file
+                .as_deref()
+                .unwrap_or("session.html");
+            return match root.file(page) {
+                Ok(page) => HttpResponse::page_server(200, &page, error_page),
+                Err(err) => HttpResponse::from_path_error(err, error_page),
+            };
         }
     }
 
     // Verification of the session for all other roads
-    let session_id = request.get_cookies().get("session_id").cloned();
-    match handle_session(&mut session_manager, session_id, &request) {
+    match check_session(&request) {
         Ok(_) => {
             if let Some(listing_enabled) = route.directory_listing {
                 if listing_enabled {
                     println!("listing_enabled == true");
-                    let response = HttpResponse::list_dir(request.path, error_page);
+                    let dir_path = match root.resolve(&request.path) {
+                        Ok(dir_path) => dir_path,
+                        Err(err) => return HttpResponse::from_path_error(err, error_page),
+                    };
+                    let response = HttpResponse::list_dir(request.path, &dir_path, error_page);
                     return response;
                 }
             }
 
-            if let Some(redirect_to) = &route.redirection {
-                return HttpResponse {
-                    status_code: 301,
-                    headers: vec![("Location".to_string(), redirect_to.clone())],
-                    body: String::new().into(),
-                };
-            }
-
             if let Some(default_file) = &route.default_file {
-                let path_str = format!("./public/{}", default_file);
-                let file_path = Path::new(&path_str);
+                let file_path = match root.file(default_file) {
+                    Ok(file_path) => file_path,
+                    Err(err) => return HttpResponse::from_path_error(err, error_page),
+                };
                 if file_path.exists() {
                     if let Some(cgi) = &route.cgi {
+                        let path_str = file_path.to_string_lossy();
                         let cgi_handler = CGIHandler::new(cgi, &path_str, &request.headers);
                         return match cgi_handler.handle_request(&request) {
-                            Ok(output) => HttpResponse::from_cgi_output(output, error_page),
+                            Ok(output) => HttpResponse::from_cgi_output(output),
                             Err(_) => HttpResponse::internal_server_error(error_page),
                         };
                     }
 
-                    let response = HttpResponse::page_server(200, &default_file, error_page);
+                    let response = HttpResponse::page_server(200, &file_path, error_page);
                     return response;
                 } else {
                     println!("file_path.exists() === false")
                 }
+                println!("Not found (handle_route)");
+                return HttpResponse::not_found(error_page);
             };
 
-            println!("Not found (handle_route)");
-            HttpResponse::not_found(error_page)
+            // Without a default_file, the route path is served like the files below it
+            HttpResponse::serve_files(request, route, root, error_page)
         }
         Err(redirect_response) => redirect_response,
     }
 }
+
+// A request for a file below a route, or matched by a regex route: the output of the
+// route's CGI for a script, or else the file, found by `index` or `try_files`.
+// Scripts need a session like the route itself; files, like other static files, don't.
+pub fn handle_route_file(
+    route: &RouteConfig,
+    request: HttpRequest,
+    root: &DocumentRoot,
+    error_page: Option<ErrorPages>,
+) -> HttpResponse {
+    let file_path = match root.resolve(&request.path) {
+        Ok(file_path) => file_path,
+        Err(err) => return HttpResponse::from_path_error(err, error_page),
+    };
+
+    if let Some(cgi) = &route.cgi {
+        if file_path.is_file() {
+            if let Err(redirect_response) = check_session(&request) {
+                return redirect_response;
+            }
+            let path_str = file_path.to_string_lossy();
+            let cgi_handler = CGIHandler::new(cgi, &path_str, &request.headers);
+            return match cgi_handler.handle_request(&request) {
+                Ok(output) => HttpResponse::from_cgi_output(output),
+                Err(_) => HttpResponse::internal_server_error(error_page),
+            };
+        }
+    }
+
+    HttpResponse::serve_files(request, route, root, error_page)
+}
+
+// The redirection of a route, for the route itself and every path below it. In the
+// target, $rest stands for the part of the path below the route, and $name or ${name}
+// for a parameter of the route ($1 for the first group of a regex).
+pub fn handle_redirection(route: &RouteMatch, request: &HttpRequest) -> Option<HttpResponse> {
+    let template = route.config.redirection.as_ref()?;
+    let rest = request
+        .path
+        .strip_prefix(route.prefix)
+        .unwrap_or(&request.path);
+
+    let mut location = expand_variables(template, |name| {
+        let value = match request.param(name) {
+            Some(value) => value,
+            None if name == "rest" => rest,
+            // A group of the regex that took no part in the match: the router
+            // refuses redirections with any other unknown name
+            None => "",
+        };
+        percent_encode_path(value)
+    });
+
+    if route.config.redirect_query == Some(true) {
+        if let Some(query) = &request.query {
+            location.push(if location.contains('?') { '&' } else { '?' });
+            location.push_str(query);
+        }
+    }
+
+    let status = route.config.redirect_status.unwrap_or(301);
+    Some(HttpResponse::redirect(status, &location))
+}
@@ -1,99 +1,122 @@
-use std::collections::HashMap;
 use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::{Child, ChildStdout, Command, Stdio};
 
+use crate::headers::Headers;
 use crate::http_request::HttpRequest;
 
 pub struct CGIHandler {
     pub script_path: String,
     pub cgi_executable: String,
     pub content_length: Option<usize>,
-    pub is_chunked: bool,
 }
 
 impl CGIHandler {
-    pub fn new(cgi_executable: &str, script_path: &str, headers: &HashMap<String, String>) -> Self {
+    pub fn new(cgi_executable: &str, script_path: &str, headers: &Headers) -> Self {
         CGIHandler {
             script_path: script_path.to_string(),
             cgi_executable: cgi_executable.to_string(),
             content_length: headers.get("Content-Length").and_then(|l| l.parse().ok()),
-            is_chunked: headers
-                .get("Transfer-Encoding")
-                .map_or(false, |t| t.to_lowercase() == "chunked"),
         }
     }
 
-    pub fn execute(&self, request_body: &[u8]) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+    pub fn execute(
+        &self,
+        method: &str,
+        query: Option<&str>,
+        params: &[(String, String)],
+        request_body: &[u8],
+    ) -> std::io::Result<CgiOutput> {
         let full_path = Path::new(&self.script_path);
 
         let mut command = Command::new(&self.cgi_executable);
         command
-            .arg(&full_path)
+            .arg(full_path)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
+            .stderr(Stdio::inherit()) // Errors of the script go to the server log
             .env("CONTENT_LENGTH", request_body.len().to_string())
             .env("CONTENT_TYPE", "application/x-www-form-urlencoded")
-            .env("REQUEST_METHOD", "POST")
+            .env("REQUEST_METHOD", method)
+            .env("QUERY_STRING", query.unwrap_or_default())
             .env("SCRIPT_FILENAME", full_path.to_str().unwrap_or(""))
             .env("SCRIPT_NAME", &self.script_path);
+        for (name, value) in params {
+            command.env(param_variable(name), value);
+        }
 
         let mut child = command.spawn()?;
 
+        // Written from a thread: a script may answer before reading all of its input,
+        // and the pipe only holds so much
         if let Some(mut stdin) = child.stdin.take() {
-            stdin.write_all(request_body)?;
+            let body = request_body.to_vec();
+            std::thread::spawn(move || {
+                let _ = stdin.write_all(&body);
+            });
         }
 
-        let output = child.wait_with_output()?;
-        Ok((output.stdout, output.stderr))
-    }
-
-    pub fn handle_request(&self, request: &HttpRequest) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
-        if self.is_chunked {
-            self.handle_chunked(&request.body)
-        } else {
-            self.handle_unchunked(&request.body)
+        let stdout = match child.stdout.take() {
+            Some(stdout) => stdout,
+            None => return Err(std::io::Error::other("CGI stdout is not piped")),
+        };
+        let flags = unsafe { libc::fcntl(stdout.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0
+            || unsafe { libc::fcntl(stdout.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) }
+                < 0
+        {
+            return Err(std::io::Error::last_os_error());
         }
+        Ok(CgiOutput { child, stdout })
     }
 
-    fn handle_chunked(&self, input: &[u8]) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
-        let mut reader = std::io::Cursor::new(input);
-        let mut body = Vec::new();
-
-        while let Ok(chunk_size) = read_chunk_size(&mut reader) {
-            if chunk_size == 0 {
-                break;
-            }
-
-            let mut chunk = vec![0; chunk_size];
-            reader.read_exact(&mut chunk)?;
-            body.extend(chunk);
+    // The body was already decoded by the connection, whatever its transfer coding
+    pub fn handle_request(&self, request: &HttpRequest) -> std::io::Result<CgiOutput> {
+        self.execute(
+            &request.method,
+            request.query.as_deref(),
+            &request.params,
+            &request.body,
+        )
+    }
+}
 
-            // Skip CRLF
-            reader.read_exact(&mut [0; 2])?;
-        }
+// Standard output of a running CGI script, streamed to the client as it is produced.
+// Reads fail with WouldBlock while the script has nothing new to say.
+pub struct CgiOutput {
+    child: Child,
+    stdout: ChildStdout,
+}
 
-        self.execute(&body)
+impl Read for CgiOutput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdout.read(buf)
     }
+}
 
-    fn handle_unchunked(&self, input: &[u8]) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
-        self.execute(input)
+impl AsRawFd for CgiOutput {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stdout.as_raw_fd()
     }
 }
 
-fn read_chunk_size(input: &mut impl Read) -> std::io::Result<usize> {
-    let mut size_bytes = Vec::new();
-    let mut byte = [0u8; 1];
-
-    // Read until CRLF
-    while input.read_exact(&mut byte).is_ok() && byte[0] != b'\n' {
-        if byte[0] != b'\r' {
-            size_bytes.push(byte[0]);
+impl Drop for CgiOutput {
+    // The output is sent, or the client is gone: stop the script if it still runs
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
         }
+        let _ = self.child.wait();
     }
+}
 
-    // Parse hexadecimal size
-    let size_str = String::from_utf8_lossy(&size_bytes);
-    Ok(usize::from_str_radix(size_str.trim(), 16).unwrap_or(0))
+// Environment variable of a route parameter: ROUTE_PARAM_ID for ":id", ROUTE_PARAM_1
+// for the first group of a regex
+fn param_variable(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("ROUTE_PARAM_{}", name)
 }
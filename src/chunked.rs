@@ -0,0 +1,155 @@
+// Longest chunk-size or trailer line accepted
+const MAX_LINE: usize = 8192;
+
+// Result of feeding the bytes received so far to a ChunkedDecoder
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeStatus {
+    NeedMore, // Every available byte was consumed, the body is not finished
+    Done,     // The last chunk and the trailers were read
+    Invalid,  // Malformed chunked framing
+    TooLarge, // The decoded body would go over the size limit
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Size,      // Expecting a chunk-size line, with optional extensions
+    Data(u64), // Bytes left in the current chunk
+    DataEnd,   // Expecting the CRLF that closes a chunk
+    Trailers,  // After the last chunk, until the empty line
+}
+
+// Incremental decoder for a request body sent with Transfer-Encoding: chunked.
+// Input may stop anywhere, even in the middle of a chunk-size line: decoding
+// resumes where it stopped when more bytes arrive.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: State,
+    pub trailers: Vec<(String, String)>,
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::Size,
+            trailers: Vec::new(),
+        }
+    }
+
+    // Decode what `input` holds into `body`, removing the consumed bytes from `input`.
+    // Bytes after the end of the body are left in `input`.
+    pub fn decode(
+        &mut self,
+        input: &mut Vec<u8>,
+        body: &mut Vec<u8>,
+        limit: Option<usize>,
+    ) -> DecodeStatus {
+        let mut pos = 0;
+        let status = loop {
+            match self.state {
+                State::Size => {
+                    let line = match next_line(&input[pos..]) {
+                        Ok(Some(line)) => line,
+                        Ok(None) => break DecodeStatus::NeedMore,
+                        Err(()) => break DecodeStatus::Invalid,
+                    };
+                    pos += line.len() + 2;
+
+                    // Chunk extensions (";name=value") carry nothing we use
+                    let size = line.split(|&b| b == b';').next().unwrap_or_default();
+                    let size = match parse_chunk_size(size) {
+                        Some(size) => size,
+                        None => break DecodeStatus::Invalid,
+                    };
+                    if exceeds(limit, (body.len() as u64).saturating_add(size)) {
+                        break DecodeStatus::TooLarge;
+                    }
+                    self.state = if size == 0 {
+                        State::Trailers
+                    } else {
+                        State::Data(size)
+                    };
+                }
+                State::Data(left) => {
+                    let available = (input.len() - pos) as u64;
+                    if available == 0 {
+                        break DecodeStatus::NeedMore;
+                    }
+                    let take = left.min(available) as usize;
+                    body.extend_from_slice(&input[pos..pos + take]);
+                    pos += take;
+                    self.state = match left - take as u64 {
+                        0 => State::DataEnd,
+                        left => State::Data(left),
+                    };
+                }
+                State::DataEnd => {
+                    if input.len() - pos < 2 {
+                        break DecodeStatus::NeedMore;
+                    }
+                    if &input[pos..pos + 2] != b"\r\n" {
+                        break DecodeStatus::Invalid;
+                    }
+                    pos += 2;
+                    self.state = State::Size;
+                }
+                State::Trailers => {
+                    let line = match next_line(&input[pos..]) {
+                        Ok(Some(line)) => line,
+                        Ok(None) => break DecodeStatus::NeedMore,
+                        Err(()) => break DecodeStatus::Invalid,
+                    };
+                    pos += line.len() + 2;
+                    if line.is_empty() {
+                        break DecodeStatus::Done;
+                    }
+
+                    let line = match std::str::from_utf8(line) {
+                        Ok(line) => line,
+                        Err(_) => break DecodeStatus::Invalid,
+                    };
+                    match line.split_once(':') {
+                        Some((name, value)) if !name.is_empty() && !name.contains(' ') => {
+                            self.trailers
+                                .push((name.to_string(), value.trim().to_string()));
+                        }
+                        _ => break DecodeStatus::Invalid,
+                    }
+                }
+            }
+        };
+
+        input.drain(..pos);
+        status
+    }
+}
+
+impl Default for ChunkedDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The line at the start of `input`, without its CRLF; None if it is not complete yet
+fn next_line(input: &[u8]) -> Result<Option<&[u8]>, ()> {
+    match input.windows(2).position(|window| window == b"\r\n") {
+        Some(end) if end <= MAX_LINE => Ok(Some(&input[..end])),
+        Some(_) => Err(()),
+        None if input.len() > MAX_LINE => Err(()),
+        None => Ok(None),
+    }
+}
+
+// Hexadecimal chunk size, optionally followed by whitespace before extensions
+fn parse_chunk_size(size: &[u8]) -> Option<u64> {
+    let size = std::str::from_utf8(size)
+        .ok()?
+        .trim_end_matches([' ', '\t']);
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(size, 16).ok()
+}
+
+fn exceeds(limit: Option<usize>, len: u64) -> bool {
+    limit.is_some_and(|limit| len > limit as u64)
+}
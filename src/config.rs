@@ -1,15 +1,22 @@
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::net::TcpListener;
+use std::net::{SocketAddr, TcpListener};
 use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use std::{
     fs, io,
-    os::fd::{AsRawFd, RawFd},
+    os::fd::{AsRawFd, FromRawFd, RawFd},
 };
 
 // use crate::cgi::{list_directory,handle_route};
 
-use crate::event_loop::EventLoop;
+use crate::connection::{HeaderLimits, Timeouts, DEFAULT_MAX_REQUESTS};
+use crate::event_loop::{Control, Controller, EventLoop, ServerSetup, WorkerSetup};
+use crate::path_resolver::{DocumentRoot, DEFAULT_ROOT};
+use crate::router::Router;
+use crate::signals::SignalListener;
 
 use libc::{fcntl, F_GETFL, F_SETFL, O_NONBLOCK};
 
@@ -18,28 +25,114 @@ pub struct ServerConfig {
     pub name: String,
     pub addr: String,
     pub ports: Vec<String>,
+    pub root: Option<String>, // Ex: "public", directory the files of this server are served from
     pub routes: HashMap<String, RouteConfig>,
     pub error_pages: Option<HashMap<u16, String>>, // Ex: 404 -> "/path/to/404.html"
     pub client_body_size_limit: Option<usize>,     // Ex: Limite d'upload en octets
+    pub keepalive_timeout: Option<u64>,            // Ex: 5 seconds idle between two requests
+    pub header_timeout: Option<u64>,               // Ex: 10 seconds to receive the request headers
+    pub body_timeout: Option<u64>,                 // Ex: 30 seconds without progress on the body
+    pub send_timeout: Option<u64>,                 // Ex: 30 seconds without progress on the response
+    pub keepalive_requests: Option<usize>,         // Ex: 100 requests per connection
+    pub request_line_limit: Option<usize>,         // Ex: 8192 bytes, else 414
+    pub header_line_limit: Option<usize>,          // Ex: 8192 bytes for one header, else 431
+    pub header_size_limit: Option<usize>,          // Ex: 32768 bytes for all headers, else 431
+    pub header_count_limit: Option<usize>,         // Ex: 100 headers, else 431
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl ServerConfig {
+    // Configured timeouts, falling back on the defaults for the missing ones
+    pub fn timeouts(&self) -> Timeouts {
+        let defaults = Timeouts::default();
+        let secs =
+            |value: Option<u64>, default: Duration| value.map_or(default, Duration::from_secs);
+        Timeouts {
+            keepalive: secs(self.keepalive_timeout, defaults.keepalive),
+            header: secs(self.header_timeout, defaults.header),
+            body: secs(self.body_timeout, defaults.body),
+            send: secs(self.send_timeout, defaults.send),
+        }
+    }
+
+    // Configured header limits, falling back on the defaults for the missing ones
+    pub fn header_limits(&self) -> HeaderLimits {
+        let defaults = HeaderLimits::default();
+        HeaderLimits {
+            request_line: self.request_line_limit.unwrap_or(defaults.request_line),
+            header_line: self.header_line_limit.unwrap_or(defaults.header_line),
+            total: self.header_size_limit.unwrap_or(defaults.total),
+            count: self.header_count_limit.unwrap_or(defaults.count),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct RouteConfig {
     pub accepted_methods: Option<Vec<String>>, // Ex: ["GET", "POST"]
-    pub redirection: Option<String>,           // Ex: "/old" -> "/new"
+    pub redirection: Option<String>,           // Ex: "/new$rest", $rest being the path below the route
+    pub redirect_status: Option<u16>,          // Ex: 308 to keep the method and body (default: 301)
+    pub redirect_query: Option<bool>,          // Ex: true to pass the query string on (default: false)
     pub default_file: Option<String>,          // Ex: "index.html"
+    pub index: Option<Vec<String>>,            // Ex: ["index.html", "index.htm"], tried for a directory
+    pub try_files: Option<Vec<String>>,        // Ex: ["$uri", "$uri.html", "/index.html"], the last one as fallback
     pub cgi: Option<String>,                   // Ex: Extension ".py" -> "/path/to/python"
     pub directory_listing: Option<bool>,
+    pub client_body_size_limit: Option<usize>, // Ex: Replaces the limit of the server for this route
+    pub root: Option<String>,                  // Ex: "site2", replaces the root of the server for this route
+    pub alias: Option<String>,                 // Ex: "/srv/assets" in place of the route path
+    pub rewrites: Option<Vec<RewriteRule>>,    // Ex: internal rewrites, tried in order
+}
+
+// Internal rewrite of the request path, without a round-trip to the client
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RewriteRule {
+    pub pattern: String,      // Ex: "^/blog/(\\d+)$"
+    pub replacement: String,  // Ex: "/cgi-bin/blog.py?id=$1"
+    pub flag: Option<String>, // Ex: "last" to route again at once, "break" to stay in this route
+}
+
+impl RouteConfig {
+    // Where the files of the route `path` are looked up, given the root of its server
+    pub fn document_root(&self, path: &str, server_root: &str) -> DocumentRoot {
+        match (&self.alias, &self.root) {
+            (Some(alias), _) => DocumentRoot::alias(alias, path),
+            (None, Some(root)) => DocumentRoot::root(root),
+            (None, None) => DocumentRoot::root(server_root),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub servers: Vec<ServerConfig>,
+    pub workers: Option<usize>, // Ex: 4 threads, each with its own epoll instance
+    pub drain_timeout: Option<u64>, // Ex: 30 seconds to finish in-flight requests on shutdown
+    #[serde(skip)]
+    pub path: String, // File the configuration was read from, read again on SIGHUP
 }
 
+// Methods a route may list in accepted_methods
+const KNOWN_METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS"];
+
+// An entry of `try_files`: a path, "$uri" standing for the request path, or a status
+// like "=404" (only useful as the last entry)
+fn is_try_file(entry: &str) -> bool {
+    match entry.strip_prefix('=') {
+        Some(status) => status
+            .parse::<u16>()
+            .is_ok_and(|status| (400..600).contains(&status)),
+        None => entry.starts_with('/') || entry.starts_with("$uri"),
+    }
+}
+
+// Read and check a configuration file: one that fails validate() is an InvalidData error
 pub fn load_config(file_path: &str) -> io::Result<Config> {
     let config_data = fs::read_to_string(file_path)?;
-    let config: Config = serde_json::from_str(&config_data)?;
+    let mut config: Config = serde_json::from_str(&config_data)?;
+    config
+        .validate()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    config.path = file_path.to_string();
     Ok(config)
 }
 
@@ -55,20 +148,342 @@ fn set_non_blocking(fd: RawFd) -> std::io::Result<()> {
     Ok(())
 }
 
+// Bind a listening socket with SO_REUSEPORT so that every worker can own one
+// on the same address and let the kernel spread new connections between them.
+pub fn bind_reuseport(address: &str) -> io::Result<TcpListener> {
+    let addr: SocketAddr = address
+        .parse()
+        .map_err(|_| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // From now on the socket is closed when `listener` is dropped, even on error
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+    let enable: libc::c_int = 1;
+    for option in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &enable as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let res = match addr {
+        SocketAddr::V4(v4) => {
+            let sockaddr = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: v4.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(v4.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe {
+                libc::bind(
+                    fd,
+                    &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        SocketAddr::V6(v6) => {
+            let sockaddr = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: v6.port().to_be(),
+                sin6_flowinfo: v6.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: v6.ip().octets(),
+                },
+                sin6_scope_id: v6.scope_id(),
+            };
+            unsafe {
+                libc::bind(
+                    fd,
+                    &sockaddr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if unsafe { libc::listen(fd, libc::SOMAXCONN) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(listener)
+}
+
 impl Config {
-    // To run all valide config in our server
+    // Check the values serde can't check by itself. Every load goes through it, at
+    // startup as before a reload, so the whole file is rejected on error.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.servers.is_empty() {
+            return Err("no server defined".to_string());
+        }
+        if self.workers == Some(0) {
+            return Err("'workers' must be at least 1".to_string());
+        }
+
+        for server in &self.servers {
+            if server.name.trim().is_empty() {
+                return Err("a server has an empty name".to_string());
+            }
+            if server.addr.parse::<std::net::IpAddr>().is_err() {
+                return Err(format!(
+                    "server '{}': invalid address '{}'",
+                    server.name, server.addr
+                ));
+            }
+            for port in &server.ports {
+                if !matches!(port.parse::<u16>(), Ok(p) if p != 0) {
+                    return Err(format!("server '{}': invalid port '{}'", server.name, port));
+                }
+            }
+            for code in server.error_pages.iter().flat_map(|pages| pages.keys()) {
+                if !(400..600).contains(code) {
+                    return Err(format!(
+                        "server '{}': error page for non-error status {}",
+                        server.name, code
+                    ));
+                }
+            }
+            let timeouts = [
+                ("keepalive_timeout", server.keepalive_timeout),
+                ("header_timeout", server.header_timeout),
+                ("body_timeout", server.body_timeout),
+                ("send_timeout", server.send_timeout),
+            ];
+            for (option, value) in timeouts {
+                if value == Some(0) {
+                    return Err(format!(
+                        "server '{}': '{}' must be at least 1 second",
+                        server.name, option
+                    ));
+                }
+            }
+            let counts = [
+                ("keepalive_requests", server.keepalive_requests),
+                ("request_line_limit", server.request_line_limit),
+                ("header_line_limit", server.header_line_limit),
+                ("header_size_limit", server.header_size_limit),
+                ("header_count_limit", server.header_count_limit),
+            ];
+            for (option, value) in counts {
+                if value == Some(0) {
+                    return Err(format!(
+                        "server '{}': '{}' must be at least 1",
+                        server.name, option
+                    ));
+                }
+            }
+            if server.root.as_deref().is_some_and(|root| root.trim().is_empty()) {
+                return Err(format!("server '{}': 'root' is empty", server.name));
+            }
+            if let Err(e) = Router::new(&server.routes) {
+                return Err(format!("server '{}': {}", server.name, e));
+            }
+            for (path, route) in &server.routes {
+                if route.root.is_some() && route.alias.is_some() {
+                    return Err(format!(
+                        "server '{}': route '{}' sets both 'root' and 'alias'",
+                        server.name, path
+                    ));
+                }
+                if route
+                    .index
+                    .iter()
+                    .flatten()
+                    .any(|name| name.is_empty() || name.contains(['/', '\\']))
+                {
+                    return Err(format!(
+                        "server '{}': route '{}' has an 'index' that is not a file name",
+                        server.name, path
+                    ));
+                }
+                if let Some(try_files) = &route.try_files {
+                    if let Some(entry) = try_files.iter().find(|entry| !is_try_file(entry)) {
+                        return Err(format!(
+                            "server '{}': route '{}' has an invalid 'try_files' entry '{}'",
+                            server.name, path, entry
+                        ));
+                    }
+                    if try_files.is_empty() {
+                        return Err(format!(
+                            "server '{}': route '{}' has an empty 'try_files'",
+                            server.name, path
+                        ));
+                    }
+                }
+                let dirs = [route.root.as_deref(), route.alias.as_deref()];
+                if dirs.iter().flatten().any(|dir| dir.trim().is_empty()) {
+                    return Err(format!(
+                        "server '{}': route '{}' has an empty 'root' or 'alias'",
+                        server.name, path
+                    ));
+                }
+                for method in route.accepted_methods.iter().flatten() {
+                    if !KNOWN_METHODS.contains(&method.as_str()) {
+                        return Err(format!(
+                            "server '{}': route '{}' accepts unknown method '{}'",
+                            server.name, path, method
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // To run all valide config in our server, on as many worker threads as configured,
+    // until SIGINT or SIGTERM asks for a graceful shutdown. SIGHUP reloads the file.
     pub fn start(&self) -> std::io::Result<()> {
-        let mut event_loop = EventLoop::new()?;
-        let mut listener_list = Vec::new();
+        // Must happen before spawning the workers so that they inherit the signal mask
+        let signals = SignalListener::install()?;
+
+        let workers = self.workers.unwrap_or(1).max(1);
+        let mut handles = Vec::new();
+        let mut controllers = Vec::new();
+        let mut bound_addresses = HashSet::new();
+
+        for worker in 0..workers {
+            let setup = self.build_worker(worker == 0, &HashSet::new())?;
+            if worker == 0 {
+                bound_addresses = setup
+                    .new_listeners
+                    .iter()
+                    .map(|(address, _)| address.clone())
+                    .collect();
+            }
+
+            let mut event_loop = EventLoop::new()?;
+            event_loop.apply_setup(setup)?;
+            controllers.push(event_loop.controller());
+
+            let handle = thread::Builder::new()
+                .name(format!("worker-{}", worker))
+                .spawn(move || {
+                    if let Err(e) = event_loop.run() {
+                        eprintln!("ERROR: running server: {:?}", e);
+                    }
+                })?;
+            handles.push(handle);
+        }
+
+        let mut shutting_down = false;
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            match signals.wait(1000)? {
+                Some(libc::SIGINT) | Some(libc::SIGTERM) if shutting_down => {
+                    eprintln!("Second signal received, exiting immediately");
+                    std::process::exit(1);
+                }
+                Some(libc::SIGINT) | Some(libc::SIGTERM) => {
+                    println!("Shutting down: waiting for in-flight requests...");
+                    shutting_down = true;
+                    for controller in &controllers {
+                        controller.send(Control::Shutdown);
+                    }
+                }
+                Some(libc::SIGHUP) if !shutting_down => {
+                    match self.reload(&controllers, &bound_addresses) {
+                        Ok(addresses) => {
+                            bound_addresses = addresses;
+                            println!("Configuration reloaded from '{}'", self.path);
+                        }
+                        Err(e) => eprintln!(
+                            "ERROR: configuration reload rejected, keeping the current one: {}",
+                            e
+                        ),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for handle in handles {
+            if handle.join().is_err() {
+                eprintln!("ERROR: a worker thread panicked");
+            }
+        }
+        Ok(())
+    }
+
+    // Read the configuration file again and hand every worker its new setup.
+    // Nothing is sent unless the file is valid and every worker could be prepared.
+    // Returns the addresses the workers listen on from now on.
+    fn reload(
+        &self,
+        controllers: &[Controller],
+        bound_addresses: &HashSet<String>,
+    ) -> Result<HashSet<String>, String> {
+        let new_config = load_config(&self.path).map_err(|e| e.to_string())?;
+        if new_config.workers.unwrap_or(1).max(1) != controllers.len() {
+            eprintln!("WARNING: changing 'workers' requires a restart, ignoring it");
+        }
+
+        let mut setups = Vec::new();
+        for worker in 0..controllers.len() {
+            let setup = new_config
+                .build_worker(worker == 0, bound_addresses)
+                .map_err(|e| e.to_string())?;
+            setups.push(setup);
+        }
+
+        let addresses = setups
+            .first()
+            .map(|setup| {
+                setup
+                    .servers
+                    .iter()
+                    .flat_map(|server| server.addresses.iter().cloned())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        for (controller, setup) in controllers.iter().zip(setups) {
+            controller.send(Control::Reload(setup));
+        }
+        Ok(addresses)
+    }
+
+    // Prepare what one worker serves, binding the listeners it doesn't have yet.
+    // `already_bound` are the addresses the worker still listens on from a previous setup.
+    // Only the first worker reports what it does, the others bind the same addresses.
+    fn build_worker(
+        &self,
+        verbose: bool,
+        already_bound: &HashSet<String>,
+    ) -> std::io::Result<WorkerSetup> {
+        let mut servers: Vec<ServerSetup> = Vec::new();
+        let mut new_listeners = Vec::new();
         let mut server_names = HashSet::new();
         let mut server_addresses = Vec::new();
-        // let mut addesses = Vec::new();
+        // With SO_REUSEPORT a second bind on the same address succeeds, so the
+        // addresses already claimed by a server of this worker are tracked explicitly
+        let mut claimed_addresses = HashSet::new();
 
         for server in &self.servers {
 
             // Check if there's two server with the same name
             if !server_names.insert(&server.name) {
-                eprintln!("IGNORE: Duplicate server name '{}'", server.name);
+                if verbose {
+                    eprintln!("IGNORE: Duplicate server name '{}'", server.name);
+                }
                 continue;
             }
 
@@ -79,77 +494,115 @@ impl Config {
             server_routes.insert("/session".to_string(), RouteConfig {
                 accepted_methods: Some(vec!["GET".to_string()]),
                 default_file: Some("session.html".to_string()),
-                redirection: None,
-                cgi: None,
-                directory_listing: None,
+                ..RouteConfig::default()
             });
 
             server_routes.insert("/create-session".to_string(), RouteConfig {
                 accepted_methods: Some(vec!["POST".to_string()]),
                 default_file: Some("session.html".to_string()),
-                redirection: None,
-                cgi: None,
-                directory_listing: None,
+                ..RouteConfig::default()
             });
 
+            // The route table is read-only once built and shared by every connection
+            let mut setup = ServerSetup {
+                name: server.name.clone(),
+                addresses: Vec::new(),
+                router: Arc::new(
+                    Router::new(&server_routes)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+                ),
+                root: server.root.clone().unwrap_or_else(|| DEFAULT_ROOT.to_string()),
+                error_pages: server.error_pages.clone(),
+                size_limit: server.client_body_size_limit,
+                timeouts: server.timeouts(),
+                max_requests: server.keepalive_requests.unwrap_or(DEFAULT_MAX_REQUESTS),
+                header_limits: server.header_limits(),
+            };
+            let mut reachable = false;
+
             for port in &server.ports {
                 let address = format!("{}:{}", server.addr, port);
 
                 // Check if there's two listener with the same addresses within the same server
                 if server_addresses.contains(&(server.name.clone(), address.clone())) {
-                    eprintln!(
-                        "IGNORE: Address '{}' for server '{}' already exists.",
-                        address, server.name
-                    );
+                    if verbose {
+                        eprintln!(
+                            "IGNORE: Address '{}' for server '{}' already exists.",
+                            address, server.name
+                        );
+                    }
                     continue;
                 }
 
                 server_addresses.push((server.name.clone(), address.clone()));
 
+                let bind_result = if claimed_addresses.contains(&address) {
+                    Err(io::Error::from(io::ErrorKind::AddrInUse))
+                } else if already_bound.contains(&address) {
+                    Ok(None)
+                } else {
+                    bind_reuseport(&address).map(Some)
+                };
 
-                let listener = match TcpListener::bind(&address) {
+                let listener = match bind_result {
                     Ok(listener) => listener,
                     Err(err) => {
                         match err.kind() {
                             std::io::ErrorKind::AddrInUse => {
                                 Self::add_to_hosts(&server.name, &server.addr)?;
-                                println!(
-                                    "Server '{}' launched at: http://{}",
-                                    server.name, address
-                                );
-                                event_loop.add_server(server.name.clone(), server.routes.clone(), server.error_pages.clone(), server.client_body_size_limit);
+                                if verbose {
+                                    println!(
+                                        "Server '{}' launched at: http://{}",
+                                        server.name, address
+                                    );
+                                }
+                                reachable = true;
                             }
                             std::io::ErrorKind::AddrNotAvailable => {
-                                eprintln!(
-                                    "IGNORE: Address '{}' for server '{}' is not valid or not available.",
-                                    address, server.name
-                                );
+                                if verbose {
+                                    eprintln!(
+                                        "IGNORE: Address '{}' for server '{}' is not valid or not available.",
+                                        address, server.name
+                                    );
+                                }
                             }
                             _ => {
-                                eprintln!(
-                                    "IGNORE: Failed to bind to address '{}' for server '{}' due to: {:?}",
-                                    address, server.name, err
-                                );
+                                if verbose {
+                                    eprintln!(
+                                        "IGNORE: Failed to bind to address '{}' for server '{}' due to: {:?}",
+                                        address, server.name, err
+                                    );
+                                }
                             }
                         }
                         continue;
                     }
                 };
 
-                set_non_blocking(listener.as_raw_fd())?;
+                if let Some(listener) = listener {
+                    set_non_blocking(listener.as_raw_fd())?;
+                    new_listeners.push((address.clone(), listener));
+                }
+                claimed_addresses.insert(address.clone());
 
                 Self::add_to_hosts(&server.name, &server.addr)?;
-                println!("Server '{}' launched at: http://{}", server.name, address);
-                // let routes = server.routes.clone();
-                event_loop.add_listener(&listener, server.name.clone(), server_routes.clone(), server.error_pages.clone(), server.client_body_size_limit)?;
-                listener_list.push(listener);
+                if verbose {
+                    println!("Server '{}' launched at: http://{}", server.name, address);
+                }
+                setup.addresses.push(address);
+                reachable = true;
+            }
+
+            if reachable {
+                servers.push(setup);
             }
         }
 
-        if let Err(e) = event_loop.run(listener_list) {
-            eprintln!("ERROR: running server: {:?}", e);
-        };
-        Ok(())
+        Ok(WorkerSetup {
+            servers,
+            new_listeners,
+            drain_timeout: Duration::from_secs(self.drain_timeout.unwrap_or(30)),
+        })
     }
 
     fn add_to_hosts(name: &str, ip: &str) -> io::Result<()> {
@@ -177,10 +630,7 @@ impl Config {
                 "Failed to add to hosts file: {}",
                 String::from_utf8_lossy(&output.stderr)
             );
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Failed to execute sudo command",
-            ));
+            return Err(io::Error::other("Failed to execute sudo command"));
         }
 
         //println!("Mapping {} to {} added to hosts file.", name, ip);
@@ -0,0 +1,617 @@
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    os::fd::{AsRawFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    chunked::{ChunkedDecoder, DecodeStatus},
+    http_request::{HttpRequest, RequestError},
+    http_response::HttpResponse,
+    request_queue::RequestQueue,
+    response_body::{Framing, ResponseBody},
+};
+
+// Largest number of bytes handed to a single sendfile(2) call
+const MAX_SENDFILE: usize = 1 << 30;
+// Bytes asked from the socket by a single read(2)
+const READ_CHUNK: usize = 4096;
+// Requests served on a single connection before it is closed, unless configured
+pub const DEFAULT_MAX_REQUESTS: usize = 100;
+// Complete requests held waiting for their answer; reading stops once that many are
+// queued, so a pipelining client can't make the queue grow without bound
+const MAX_QUEUED_REQUESTS: usize = 100;
+
+// Where a connection stands in its read -> parse -> write cycle
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConnectionPhase {
+    Reading, // Waiting for (the rest of) a request
+    Writing, // A response is queued and not fully sent yet
+    Closing, // Close as soon as the pending output is flushed
+}
+
+// Which deadline a connection is currently running against
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TimeoutKind {
+    KeepAlive, // Idle between two requests
+    Header,    // Receiving the request line and headers, counted from their first byte
+    Body,      // Receiving the body, counted from the last bytes received
+    Send,      // Sending a response, counted from the last bytes the client accepted
+}
+
+// Per-server limits for each TimeoutKind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub keepalive: Duration,
+    pub header: Duration,
+    pub body: Duration,
+    pub send: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            keepalive: Duration::from_secs(5),
+            header: Duration::from_secs(10),
+            body: Duration::from_secs(30),
+            send: Duration::from_secs(30),
+        }
+    }
+}
+
+// Size limits on the request line and headers of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderLimits {
+    pub request_line: usize, // Bytes in the request line
+    pub header_line: usize,  // Bytes in a single header line
+    pub total: usize,        // Bytes in all the header lines together
+    pub count: usize,        // Number of header lines
+}
+
+impl HeaderLimits {
+    // Size of the largest head allowed: request line, header lines and their CRLFs
+    pub fn max_head(&self) -> usize {
+        self.request_line + self.total + 4
+    }
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        Self {
+            request_line: 8192,
+            header_line: 8192,
+            total: 32768,
+            count: 100,
+        }
+    }
+}
+
+// Result of trying to extract one request from the read buffer
+#[derive(Debug)]
+pub enum ParseOutcome {
+    Incomplete,
+    Headers, // The headers of a new request are parsed, its body is not read yet
+    Complete(HttpRequest),
+    Invalid,
+    TooLarge,        // The body goes over body_limit
+    UriTooLong,      // The request line goes over header_limits
+    HeadersTooLarge, // The headers go over header_limits
+    VersionNotSupported,
+}
+
+// How the body of the request being read is delimited
+#[derive(Debug)]
+pub enum BodyReader {
+    Length(usize), // Bytes of the body still to be read
+    Chunked(ChunkedDecoder),
+}
+
+impl BodyReader {
+    // None when the framing is invalid, uses a transfer coding we don't support,
+    // or is ambiguous: a request that two parsers could split differently is
+    // refused rather than guessed (RFC 9112, section 6.3)
+    fn for_request(request: &mut HttpRequest) -> Option<Self> {
+        let coding = request.headers.get_list("Transfer-Encoding");
+        let length = request.headers.get_list("Content-Length");
+
+        match (coding, length) {
+            // Each side of a proxy could pick a different one of the two
+            (Some(_), Some(_)) => None,
+            // HTTP/1.0 has no transfer codings, and chunked must be the only one
+            (Some(coding), None) => (request.is_http_1_1()
+                && coding.eq_ignore_ascii_case("chunked"))
+            .then(|| BodyReader::Chunked(ChunkedDecoder::new())),
+            (None, Some(length)) => {
+                let length = parse_content_length(&length)?;
+                request.headers.insert("Content-Length", length.to_string());
+                Some(BodyReader::Length(length))
+            }
+            (None, None) => Some(BodyReader::Length(0)),
+        }
+    }
+}
+
+// A piece of output waiting for its turn to be written
+#[derive(Debug)]
+pub struct Outgoing {
+    pub body: ResponseBody,
+    pub chunked: bool,
+    // Sent straight from the file to the socket with sendfile(2)
+    pub zero_copy: bool,
+}
+
+#[derive(Debug)]
+pub struct Connection {
+    pub stream: TcpStream,
+    pub listener_fd: RawFd,
+    pub read_buf: Vec<u8>,
+    // Bytes being written right now, and how many of them are already sent
+    pub write_buf: Vec<u8>,
+    pub write_pos: usize,
+    // Responses (heads and bodies) queued behind write_buf, in order
+    pub outgoing: VecDeque<Outgoing>,
+    pub phase: ConnectionPhase,
+    // Complete requests, answered in the order they arrived
+    pub requests: RequestQueue,
+    // Request whose headers are parsed and whose body is being read
+    pub pending: Option<(HttpRequest, BodyReader)>,
+    // Largest body accepted for the pending request, set once its headers are known
+    pub body_limit: Option<usize>,
+    // Whether EPOLLOUT is currently part of the registered interest set
+    pub write_interest: bool,
+    // The body being sent is a stream with no data ready: wait for it, not for the socket
+    pub waiting_for_body: bool,
+    pub timeouts: Timeouts,
+    pub header_limits: HeaderLimits,
+    // When the first byte of the request being read arrived (or the connection was accepted)
+    pub request_started: Option<Instant>,
+    // Last time bytes were received or sent
+    pub last_activity: Instant,
+    // Deadline the connection runs against; the timer armed in the wheel, identified
+    // by its token, fires at timer_armed, which is never later than that deadline
+    pub timer: Option<(TimeoutKind, Instant)>,
+    pub timer_token: u64,
+    pub timer_armed: Option<Instant>,
+    // Requests answered so far, and how many the connection may serve in total
+    pub requests_served: usize,
+    pub max_requests: usize,
+    // The client shut down its side; answer what was received, then close
+    pub read_closed: bool,
+    // Error that stopped parsing, sent once the requests before it are answered
+    pub final_response: Option<HttpResponse>,
+    // The pending request waits for 100 Continue, sent once the requests before it are
+    // answered, unless its body starts arriving first
+    pub send_continue: bool,
+}
+
+impl Connection {
+    pub fn new(
+        stream: TcpStream,
+        listener_fd: RawFd,
+        timeouts: Timeouts,
+        max_requests: usize,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            stream,
+            listener_fd,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            write_pos: 0,
+            outgoing: VecDeque::new(),
+            phase: ConnectionPhase::Reading,
+            requests: RequestQueue::new(MAX_QUEUED_REQUESTS),
+            pending: None,
+            body_limit: None,
+            write_interest: false,
+            waiting_for_body: false,
+            timeouts,
+            header_limits: HeaderLimits::default(),
+            request_started: Some(now),
+            last_activity: now,
+            timer: None,
+            timer_token: 0,
+            timer_armed: None,
+            requests_served: 0,
+            max_requests,
+            read_closed: false,
+            final_response: None,
+            send_continue: false,
+        }
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+
+    // Read from the socket until it would block, or until the read buffer holds all
+    // that read_limit allows. Returns false when the peer has closed its side of the
+    // connection.
+    pub fn fill_read_buf(&mut self) -> std::io::Result<bool> {
+        let mut temp_buffer = [0; READ_CHUNK];
+
+        loop {
+            let room = self.read_limit().saturating_sub(self.read_buf.len());
+            if room == 0 {
+                return Ok(true);
+            }
+            match self.stream.read(&mut temp_buffer[..room.min(READ_CHUNK)]) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.read_buf.extend_from_slice(&temp_buffer[..n]);
+                    self.last_activity = Instant::now();
+                    self.request_started.get_or_insert(self.last_activity);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Most bytes the read buffer may hold: the largest head the limits allow, so that an
+    // oversized head is refused once that much has arrived, not once all of it has been
+    // buffered. A body is read that much at a time, and no further than its length.
+    fn read_limit(&self) -> usize {
+        let limit = self.header_limits.max_head().max(READ_CHUNK);
+        match &self.pending {
+            Some((_, BodyReader::Length(left))) => limit.min(*left),
+            _ => limit,
+        }
+    }
+
+    // Take the next request out of the read buffer, keeping any bytes that
+    // belong to the following one. Headers is returned once, as soon as the
+    // headers are parsed, so that limits can be set before the body is read.
+    pub fn next_request(&mut self) -> ParseOutcome {
+        let (mut request, mut reader) = match self.pending.take() {
+            Some(pending) => pending,
+            None => {
+                // Empty lines before a request line are ignored (RFC 9112, section 2.2)
+                let blank = self
+                    .read_buf
+                    .chunks(2)
+                    .take_while(|pair| *pair == b"\r\n")
+                    .count();
+                self.read_buf.drain(..blank * 2);
+
+                let head_end = self
+                    .read_buf
+                    .windows(4)
+                    .position(|window| window == b"\r\n\r\n");
+
+                // Give up as soon as the head is too large, complete or not
+                let head = &self.read_buf[..head_end.unwrap_or(self.read_buf.len())];
+                if let Some(outcome) = check_head(head, &self.header_limits) {
+                    return outcome;
+                }
+                let headers_end = match head_end {
+                    Some(pos) => pos + 4,
+                    // No more is read for this head: it can't end within the limits
+                    None if self.read_buf.len() >= self.header_limits.max_head() => {
+                        return ParseOutcome::HeadersTooLarge
+                    }
+                    None => return ParseOutcome::Incomplete,
+                };
+
+                let head: Vec<u8> = self.read_buf.drain(..headers_end).collect();
+                let mut request = match HttpRequest::parse_head(&head, self.listener_fd, self.fd())
+                {
+                    Ok(request) => request,
+                    Err(RequestError::BadRequest) => return ParseOutcome::Invalid,
+                    Err(RequestError::VersionNotSupported) => {
+                        return ParseOutcome::VersionNotSupported
+                    }
+                };
+                let reader = match BodyReader::for_request(&mut request) {
+                    Some(reader) => reader,
+                    None => return ParseOutcome::Invalid,
+                };
+                self.pending = Some((request, reader));
+                self.body_limit = None;
+                self.send_continue = false;
+                return ParseOutcome::Headers;
+            }
+        };
+
+        // The client sends the body without waiting: 100 Continue would come too late
+        if !self.read_buf.is_empty() {
+            self.send_continue = false;
+        }
+
+        match &mut reader {
+            BodyReader::Length(left) => {
+                let take = (*left).min(self.read_buf.len());
+                request.body.extend(self.read_buf.drain(..take));
+                *left -= take;
+                if *left > 0 {
+                    self.pending = Some((request, reader));
+                    return ParseOutcome::Incomplete;
+                }
+            }
+            BodyReader::Chunked(decoder) => {
+                match decoder.decode(&mut self.read_buf, &mut request.body, self.body_limit) {
+                    DecodeStatus::NeedMore => {
+                        self.pending = Some((request, reader));
+                        return ParseOutcome::Incomplete;
+                    }
+                    DecodeStatus::Invalid => return ParseOutcome::Invalid,
+                    DecodeStatus::TooLarge => return ParseOutcome::TooLarge,
+                    DecodeStatus::Done => {}
+                }
+
+                // Handlers see a plain body of known length, trailers included as headers
+                // A trailer never replaces a header
+                for (name, value) in std::mem::take(&mut decoder.trailers) {
+                    if !request.headers.contains(&name) {
+                        request.headers.append(name, value);
+                    }
+                }
+                request.headers.remove("Transfer-Encoding");
+                request
+                    .headers
+                    .insert("Content-Length", request.body.len().to_string());
+            }
+        }
+
+        // A pipelined request may already have started
+        self.request_started = if self.read_buf.is_empty() {
+            None
+        } else {
+            Some(Instant::now())
+        };
+        ParseOutcome::Complete(request)
+    }
+
+    // Keep-Alive parameters to advertise with the response being sent: the idle
+    // timeout in seconds and how many more requests are accepted.
+    // None when that response must be the last one.
+    pub fn keep_alive_params(&self) -> Option<(u64, usize)> {
+        let remaining = self.max_requests.saturating_sub(self.requests_served);
+        if remaining == 0 {
+            return None;
+        }
+        Some((self.timeouts.keepalive.as_secs(), remaining))
+    }
+
+    // The deadline that applies to the connection in its current state
+    pub fn deadline(&self) -> Option<(TimeoutKind, Instant)> {
+        if self.has_pending_output() {
+            return Some((TimeoutKind::Send, self.last_activity + self.timeouts.send));
+        }
+        if self.phase == ConnectionPhase::Closing {
+            return None;
+        }
+        match self.request_started {
+            Some(_) if self.pending.is_some() => {
+                Some((TimeoutKind::Body, self.last_activity + self.timeouts.body))
+            }
+            Some(started) => Some((TimeoutKind::Header, started + self.timeouts.header)),
+            None => Some((
+                TimeoutKind::KeepAlive,
+                self.last_activity + self.timeouts.keepalive,
+            )),
+        }
+    }
+
+    // Between two requests: nothing received and nothing left to send
+    pub fn is_idle(&self) -> bool {
+        self.phase == ConnectionPhase::Reading
+            && self.read_buf.is_empty()
+            && self.final_response.is_none()
+            && !self.has_pending_output()
+    }
+
+    pub fn has_pending_output(&self) -> bool {
+        self.write_pos < self.write_buf.len() || !self.outgoing.is_empty()
+    }
+
+    // Queue a response behind the ones already waiting
+    pub fn queue_response(&mut self, response: HttpResponse, chunked_allowed: bool) {
+        let (head, body, framing) = response.into_parts(chunked_allowed);
+
+        self.outgoing.push_back(Outgoing {
+            body: ResponseBody::Bytes(head),
+            chunked: false,
+            zero_copy: false,
+        });
+        if !body.is_empty() {
+            // Only an untouched file body can skip the copy through userspace
+            let chunked = framing == Framing::Chunked;
+            let zero_copy = !chunked && matches!(body, ResponseBody::File { .. });
+            self.outgoing.push_back(Outgoing {
+                body,
+                chunked,
+                zero_copy,
+            });
+        }
+
+        if self.phase == ConnectionPhase::Reading {
+            self.phase = ConnectionPhase::Writing;
+        }
+    }
+
+    // Queue the interim response that tells a client sending Expect: 100-continue
+    // to go on with the body
+    pub fn queue_continue(&mut self) {
+        self.outgoing.push_back(Outgoing {
+            body: ResponseBody::Bytes(b"HTTP/1.1 100 Continue\r\n\r\n".to_vec()),
+            chunked: false,
+            zero_copy: false,
+        });
+
+        if self.phase == ConnectionPhase::Reading {
+            self.phase = ConnectionPhase::Writing;
+        }
+    }
+
+    // Load the next slice of queued output into write_buf.
+    // Returns false when there is nothing left to send.
+    fn refill_write_buf(&mut self) -> std::io::Result<bool> {
+        while let Some(current) = self.outgoing.front_mut() {
+            let chunk = current.body.next_chunk()?;
+
+            if chunk.is_empty() {
+                let chunked = current.chunked;
+                self.outgoing.pop_front();
+                if chunked {
+                    self.write_buf = b"0\r\n\r\n".to_vec();
+                    self.write_pos = 0;
+                    return Ok(true);
+                }
+                continue;
+            }
+
+            self.write_buf = if current.chunked {
+                let mut framed = format!("{:x}\r\n", chunk.len()).into_bytes();
+                framed.extend_from_slice(&chunk);
+                framed.extend_from_slice(b"\r\n");
+                framed
+            } else {
+                chunk
+            };
+            self.write_pos = 0;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // Send the file at the front of the queue with sendfile(2).
+    // Returns None when the front of the queue is not a zero-copy file,
+    // otherwise whether the file was completely sent.
+    fn send_file(&mut self) -> std::io::Result<Option<bool>> {
+        let socket_fd = self.fd();
+        let current = match self.outgoing.front_mut() {
+            Some(current) if current.zero_copy => current,
+            _ => return Ok(None),
+        };
+        let (file, offset, len) = match &mut current.body {
+            ResponseBody::File { file, offset, len } => (file, offset, len),
+            _ => return Ok(None),
+        };
+
+        while *len > 0 {
+            let mut file_offset = *offset as libc::off_t;
+            let count = (*len).min(MAX_SENDFILE as u64) as usize;
+            let sent =
+                unsafe { libc::sendfile(socket_fd, file.as_raw_fd(), &mut file_offset, count) };
+
+            if sent < 0 {
+                let err = std::io::Error::last_os_error();
+                match err.kind() {
+                    ErrorKind::WouldBlock => return Ok(Some(false)),
+                    ErrorKind::Interrupted => continue,
+                    _ if matches!(err.raw_os_error(), Some(libc::EINVAL) | Some(libc::ENOSYS)) => {
+                        // Not supported for this file: fall back to read/write
+                        current.zero_copy = false;
+                        return Ok(None);
+                    }
+                    _ => return Err(err),
+                }
+            }
+            if sent == 0 {
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "File shrank while being sent",
+                ));
+            }
+
+            *offset += sent as u64;
+            *len -= sent as u64;
+            self.last_activity = Instant::now();
+        }
+
+        self.outgoing.pop_front();
+        Ok(Some(true))
+    }
+
+    // Write as much of the pending output as the socket accepts.
+    // Returns true once everything has been sent.
+    pub fn flush(&mut self) -> std::io::Result<bool> {
+        self.waiting_for_body = false;
+        loop {
+            if self.write_pos >= self.write_buf.len() {
+                match self.send_file()? {
+                    Some(true) => continue,
+                    Some(false) => return Ok(false),
+                    None => {}
+                }
+                match self.refill_write_buf() {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        self.waiting_for_body = true;
+                        return Ok(false);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            match self.stream.write(&self.write_buf[self.write_pos..]) {
+                Ok(0) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::WriteZero,
+                        "Connection closed while writing",
+                    ))
+                }
+                Ok(n) => {
+                    self.write_pos += n;
+                    self.last_activity = Instant::now();
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.write_buf.clear();
+        self.write_pos = 0;
+        if self.phase == ConnectionPhase::Writing {
+            self.phase = ConnectionPhase::Reading;
+        }
+        Ok(true)
+    }
+}
+
+// Check the request line and header lines received so far against the limits
+fn check_head(head: &[u8], limits: &HeaderLimits) -> Option<ParseOutcome> {
+    let line_len = |line: &[u8]| line.strip_suffix(b"\r").unwrap_or(line).len();
+    let mut lines = head.split(|&b| b == b'\n');
+
+    let request_line = lines.next().unwrap_or_default();
+    if line_len(request_line) > limits.request_line {
+        return Some(ParseOutcome::UriTooLong);
+    }
+
+    let (mut count, mut total) = (0, 0);
+    for line in lines.map(line_len).filter(|&len| len > 0) {
+        count += 1;
+        total += line + 2;
+        if line > limits.header_line || count > limits.count || total > limits.total {
+            return Some(ParseOutcome::HeadersTooLarge);
+        }
+    }
+    None
+}
+
+// Content-Length is only digits. A header repeated with the same value is
+// accepted (it arrives here as "5, 5"); differing values are not.
+fn parse_content_length(value: &str) -> Option<usize> {
+    let mut lengths = value.split(',').map(|length| {
+        let length = length.trim();
+        if length.is_empty() || !length.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        length.parse::<usize>().ok()
+    });
+
+    let first = lengths.next()??;
+    for length in lengths {
+        if length? != first {
+            return None;
+        }
+    }
+    Some(first)
+}
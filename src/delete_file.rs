@@ -1,13 +1,16 @@
 use serde_json::Value;
 
+use crate::headers::Headers;
 use crate::http_request::HttpRequest;
-use crate::http_response::HttpResponse;
+use crate::http_response::{ErrorPages, HttpResponse};
+use crate::path_resolver::{resolve, DocumentRoot};
+use crate::response_body::ResponseBody;
 use std::fs;
-use std::path::Path;
 
 pub fn handle_delete(
     request: HttpRequest,
-    error_page: Option<std::collections::HashMap<u16, String>>,
+    root: &DocumentRoot,
+    error_page: Option<ErrorPages>,
 ) -> HttpResponse {
     let body_text = String::from_utf8_lossy(&request.body);
     
@@ -29,8 +32,15 @@ pub fn handle_delete(
         }
     };
 
-    let base_dir = Path::new("./public/upload");
-    let file_path = base_dir.join(file_name);
+    // Only uploaded files may be deleted
+    let base_dir = match root.file("upload") {
+        Ok(dir) => dir,
+        Err(err) => return HttpResponse::from_path_error(err, error_page),
+    };
+    let file_path = match resolve(&base_dir, file_name) {
+        Ok(path) => path,
+        Err(err) => return HttpResponse::from_path_error(err, error_page),
+    };
 
     // Check that the file exists and that it is indeed a file (and not a folder)
     if !file_path.exists() || !file_path.is_file() {
@@ -42,8 +52,8 @@ pub fn handle_delete(
         Ok(_) => {
             HttpResponse {
                 status_code: 200,
-                headers: Vec::new(),
-                body: String::new().into(),
+                headers: Headers::new(),
+                body: ResponseBody::empty(),
             }
         }
         Err(err) => {
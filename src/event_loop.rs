@@ -1,28 +1,125 @@
 use crate::{
-    config::RouteConfig, http_request::HttpRequest, http_response::HttpResponse,
-    request_queue::RequestQueue,
+    connection::{
+        BodyReader, Connection, ConnectionPhase, HeaderLimits, ParseOutcome, TimeoutKind, Timeouts,
+        DEFAULT_MAX_REQUESTS,
+    },
+    http_request::HttpRequest,
+    http_response::{ErrorPages, HttpResponse},
+    path_resolver::{normalize_path, DocumentRoot, DEFAULT_ROOT},
+    router::Router,
+    timer::TimerWheel,
 };
 use std::{
     collections::HashMap,
-    io::{Error, Read, Write},
+    io::Error,
+    path::PathBuf,
     net::{TcpListener, TcpStream},
-    os::fd::{AsRawFd, RawFd},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
+// Tag of the epoll events of streamed bodies, whose low 32 bits hold the fd of the
+// connection the body is sent on
+const STREAM_EVENT: u64 = 1 << 32;
+
+// Orders sent to a running event loop from another thread
+#[derive(Debug)]
+pub enum Control {
+    Shutdown,            // Stop accepting, finish in-flight requests, then return from run
+    Reload(WorkerSetup), // Swap the served configuration, keeping open connections
+}
+
+// Everything a worker serves, built from the configuration
+#[derive(Debug)]
+pub struct WorkerSetup {
+    pub servers: Vec<ServerSetup>,
+    // Sockets freshly bound for this worker, by address ("127.0.0.1:8080")
+    pub new_listeners: Vec<(String, TcpListener)>,
+    pub drain_timeout: Duration,
+}
+
+#[derive(Debug)]
+pub struct ServerSetup {
+    pub name: String,
+    // Addresses whose listener belongs to this server; the others it answers
+    // on are shared with the server that owns them and reached by Host name
+    pub addresses: Vec<String>,
+    pub router: Arc<Router>,
+    pub root: String, // Directory the routes without their own root or alias serve
+    pub error_pages: Option<HashMap<u16, String>>,
+    pub size_limit: Option<usize>,
+    pub timeouts: Timeouts,
+    pub max_requests: usize, // Requests served on a keep-alive connection before closing it
+    pub header_limits: HeaderLimits,
+}
+
+// Handle used to send a Control to an event loop and wake it up
+#[derive(Debug, Clone)]
+pub struct Controller {
+    sender: Sender<Control>,
+    wake_fd: Arc<OwnedFd>,
+}
+
+impl Controller {
+    pub fn send(&self, control: Control) {
+        if self.sender.send(control).is_err() {
+            return; // The event loop is already gone
+        }
+        let one: u64 = 1;
+        unsafe {
+            libc::write(
+                self.wake_fd.as_raw_fd(),
+                &one as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            );
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct EventLoop {
     pub epoll_fd: RawFd,
     pub servers: HashMap<String, Server>,
-    pub request_queues: HashMap<RawFd, RequestQueue>,
+    // Listening sockets of this worker with the address they are bound to
+    pub listeners: Vec<(String, TcpListener)>,
+    pub connections: HashMap<RawFd, Connection>,
+    // eventfd registered in epoll, written to by Controller::send
+    pub wake_fd: Arc<OwnedFd>,
+    control_sender: Sender<Control>,
+    control_receiver: Receiver<Control>,
+    // How long in-flight requests may take to finish once shutdown is requested
+    pub drain_timeout: Duration,
+    pub drain_deadline: Option<Instant>,
+    // Deadlines of the connections; see Connection::deadline
+    pub timers: TimerWheel,
+    next_timer_token: u64,
 }
 
 #[derive(Debug)]
 pub struct Server {
     pub name: String,
     pub listeners: Vec<RawFd>,
-    pub route_map: HashMap<String, RouteConfig>,
+    pub router: Arc<Router>,
+    pub root: String, // Directory the routes without their own root or alias serve
     pub error_pages: Option<HashMap<u16, String>>,
     pub size_limit: Option<usize>,
+    pub timeouts: Timeouts,
+    pub max_requests: usize, // Requests served on a keep-alive connection before closing it
+    pub header_limits: HeaderLimits,
+}
+
+impl Server {
+    // Its custom error pages, read like the templates from its root
+    fn error_pages(&self) -> Option<ErrorPages> {
+        Some(ErrorPages {
+            root: PathBuf::from(&self.root),
+            pages: self.error_pages.clone().unwrap_or_default(),
+        })
+    }
 }
 
 impl EventLoop {
@@ -31,49 +128,100 @@ impl EventLoop {
         if epoll_fd < 0 {
             return Err(std::io::Error::last_os_error());
         }
+
+        let raw_wake_fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if raw_wake_fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let wake_fd = Arc::new(unsafe { OwnedFd::from_raw_fd(raw_wake_fd) });
+
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: raw_wake_fd as u64,
+        };
+        let res =
+            unsafe { libc::epoll_ctl(epoll_fd, libc::EPOLL_CTL_ADD, raw_wake_fd, &mut event) };
+        if res < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let (control_sender, control_receiver) = mpsc::channel();
         Ok(Self {
             epoll_fd,
             servers: HashMap::new(),
-            request_queues: HashMap::new(),
+            listeners: Vec::new(),
+            connections: HashMap::new(),
+            wake_fd,
+            control_sender,
+            control_receiver,
+            drain_timeout: Duration::from_secs(30),
+            drain_deadline: None,
+            timers: TimerWheel::new(),
+            next_timer_token: 0,
         })
     }
 
-    pub fn add_server(
-        &mut self,
-        server_name: String,
-        routes: HashMap<String, RouteConfig>,
-        error_pages: Option<HashMap<u16, String>>,
-        size_limit: Option<usize>,
-    ) {
-        if self.servers.contains_key(&server_name) {
-            eprintln!(
-                "Server with name '{}' already exists. Skipping addition.",
-                server_name
-            );
-            return;
+    // Get a handle to control this event loop from another thread
+    pub fn controller(&self) -> Controller {
+        Controller {
+            sender: self.control_sender.clone(),
+            wake_fd: self.wake_fd.clone(),
         }
+    }
+
+    // Serve the given configuration: listeners whose address is no longer used are
+    // closed, new ones are registered and the server table is replaced in one go.
+    // Connections already accepted stay open and use the new table from their next request.
+    pub fn apply_setup(&mut self, setup: WorkerSetup) -> std::io::Result<()> {
+        let wanted: Vec<&String> = setup
+            .servers
+            .iter()
+            .flat_map(|server| server.addresses.iter())
+            .collect();
+
+        let (kept, removed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.listeners)
+            .into_iter()
+            .partition(|(address, _)| wanted.contains(&address));
+        for (_, listener) in removed {
+            self.unregister_fd(listener.as_raw_fd());
+        }
+        self.listeners = kept;
 
-        self.servers.insert(
-            server_name.clone(),
-            Server {
-                name: server_name,
-                listeners: Vec::new(),
-                route_map: routes,
-                error_pages,
-                size_limit,
-            },
-        );
+        for (address, listener) in setup.new_listeners {
+            self.add_listener(address, listener)?;
+        }
+
+        let mut servers = HashMap::new();
+        for server in setup.servers {
+            let listeners = self
+                .listeners
+                .iter()
+                .filter(|(address, _)| server.addresses.contains(address))
+                .map(|(_, listener)| listener.as_raw_fd())
+                .collect();
+
+            servers.insert(
+                server.name.clone(),
+                Server {
+                    name: server.name,
+                    listeners,
+                    router: server.router,
+                    root: server.root,
+                    error_pages: server.error_pages,
+                    size_limit: server.size_limit,
+                    timeouts: server.timeouts,
+                    max_requests: server.max_requests,
+                    header_limits: server.header_limits,
+                },
+            );
+        }
+        self.servers = servers;
+        self.drain_timeout = setup.drain_timeout;
+        Ok(())
     }
 
     // Add a new listener (port) to handle by the server
-    pub fn add_listener(
-        &mut self,
-        listener: &TcpListener,
-        server_name: String,
-        routes: HashMap<String, RouteConfig>,
-        error_pages: Option<HashMap<u16, String>>,
-        size_limit: Option<usize>,
-    ) -> std::io::Result<()> {
+    fn add_listener(&mut self, address: String, listener: TcpListener) -> std::io::Result<()> {
         let mut event = libc::epoll_event {
             events: (libc::EPOLLIN | libc::EPOLLET) as u32,
             u64: listener.as_raw_fd() as u64,
@@ -87,272 +235,736 @@ impl EventLoop {
                 &mut event,
             )
         };
+        if res < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
 
-        let server = self.servers.entry(server_name.clone()).or_insert(Server {
-            name: server_name,
-            listeners: Vec::new(),
-            route_map: routes,
-            error_pages,
-            size_limit,
-        });
-
-        server.listeners.push(listener.as_raw_fd());
+        self.listeners.push((address, listener));
+        Ok(())
+    }
 
-        if res < 0 {
-            Err(std::io::Error::last_os_error())
-        } else {
-            Ok(())
+    fn unregister_fd(&self, fd: RawFd) {
+        unsafe {
+            libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut());
         }
     }
 
     // Run the server
-    pub fn run(&mut self, listeners_list: Vec<TcpListener>) -> std::io::Result<()> {
+    pub fn run(&mut self) -> std::io::Result<()> {
         let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 1024];
 
         loop {
+            // While draining, wake up in time to enforce the deadline
+            let drain_timeout = match self.drain_deadline {
+                Some(deadline) => {
+                    if self.connections.is_empty() {
+                        return Ok(());
+                    }
+                    let now = Instant::now();
+                    if now >= deadline {
+                        eprintln!(
+                            "Drain timeout reached, closing {} connection(s)",
+                            self.connections.len()
+                        );
+                        let remaining: Vec<RawFd> = self.connections.keys().copied().collect();
+                        for stream_fd in remaining {
+                            self.close_connection(stream_fd);
+                        }
+                        return Ok(());
+                    }
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+
+            // Sleep until the next drain or connection deadline, if any
+            let timeout = match (drain_timeout, self.timers.next_timeout(Instant::now())) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+            .map_or(-1, |wait| {
+                // Round up so that the deadline has passed when epoll_wait returns
+                wait.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+            });
+
             let num_events = unsafe {
-                libc::epoll_wait(self.epoll_fd, events.as_mut_ptr(), events.len() as i32, -1)
+                libc::epoll_wait(
+                    self.epoll_fd,
+                    events.as_mut_ptr(),
+                    events.len() as i32,
+                    timeout,
+                )
             };
 
             if num_events < 0 {
-                return Err(Error::last_os_error());
+                let err = Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
             }
 
-            for i in 0..num_events as usize {
-                let event = &events[i];
+            for event in events.iter().take(num_events as usize) {
                 let event_fd = event.u64 as RawFd;
+                let flags = event.events;
 
-                for listener in listeners_list.iter() {
-                    if listener.as_raw_fd() == event_fd {
-                        match listener.accept() {
-                            Ok((mut stream, _addr)) => {
-                                if let Err(e) = self.handle_connection(&mut stream, event_fd) {
-                                    eprintln!("Error handling connection: {:?}", e);
-
-                                    // 🔸 Close the connection
-                                    if let Err(shutdown_err) =
-                                        stream.shutdown(std::net::Shutdown::Both)
-                                    {
-                                        eprintln!(
-                                            "Error shutting down connection: {:?}",
-                                            shutdown_err
-                                        );
-                                    }
-                                }
-                            }
-                            Err(e) => eprintln!("Error1: {:?}", e),
-                        }
-                        break;
+                if event_fd == self.wake_fd.as_raw_fd() {
+                    self.handle_control();
+                    continue;
+                }
+
+                // A streamed body has data (or its end) ready: its source hanging up
+                // is no reason to close the connection
+                if event.u64 & STREAM_EVENT != 0 {
+                    let stream_fd = (event.u64 & u32::MAX as u64) as RawFd;
+                    if let Err(e) = self.handle_writable(stream_fd) {
+                        eprintln!("Error handling connection: {:?}", e);
+                        self.close_connection(stream_fd);
                     }
+                    self.refresh_timer(stream_fd);
+                    continue;
+                }
+
+                if self
+                    .listeners
+                    .iter()
+                    .any(|(_, listener)| listener.as_raw_fd() == event_fd)
+                {
+                    self.accept_connections(event_fd);
+                    continue;
+                }
+
+                if flags & (libc::EPOLLERR | libc::EPOLLHUP) as u32 != 0 {
+                    self.close_connection(event_fd);
+                    continue;
+                }
+
+                if let Err(e) = self.handle_event(event_fd, flags) {
+                    eprintln!("Error handling connection: {:?}", e);
+                    self.close_connection(event_fd);
                 }
+                self.refresh_timer(event_fd);
             }
+
+            self.expire_timers();
         }
     }
 
-    fn route_map(&self, fd: RawFd, hostname: String) -> HashMap<String, RouteConfig> {
-        let host = hostname.split_once(":").unwrap_or(("", "")).0;
+    // Apply the orders sent through the controllers
+    fn handle_control(&mut self) {
+        let mut counter = [0u8; 8];
+        unsafe {
+            libc::read(
+                self.wake_fd.as_raw_fd(),
+                counter.as_mut_ptr() as *mut libc::c_void,
+                counter.len(),
+            );
+        }
 
-        if let Some(server) = self
-            .servers
-            .values()
-            .find(|server| server.name.to_lowercase() == host)
-        {
-            return server.route_map.clone();
+        while let Ok(control) = self.control_receiver.try_recv() {
+            match control {
+                Control::Shutdown => self.begin_shutdown(),
+                Control::Reload(setup) => {
+                    if self.drain_deadline.is_some() {
+                        continue; // Shutting down, nothing to reload
+                    }
+                    if let Err(e) = self.apply_setup(setup) {
+                        eprintln!("ERROR: applying reloaded configuration: {:?}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    // Stop accepting, close idle keep-alive connections and let the others
+    // finish their current request until the drain timeout
+    fn begin_shutdown(&mut self) {
+        if self.drain_deadline.is_some() {
+            return;
         }
 
-        self.servers
+        for (_, listener) in std::mem::take(&mut self.listeners) {
+            self.unregister_fd(listener.as_raw_fd());
+        }
+
+        let idle: Vec<RawFd> = self
+            .connections
             .values()
-            .find(|server| server.listeners.contains(&fd))
-            .map(|server| server.route_map.clone())
-            .unwrap_or_default()
+            .filter(|connection| connection.is_idle())
+            .map(|connection| connection.fd())
+            .collect();
+        for stream_fd in idle {
+            self.close_connection(stream_fd);
+        }
+
+        self.drain_deadline = Some(Instant::now() + self.drain_timeout);
     }
 
-    fn get_error_pages(&self, fd: RawFd, hostname: String) -> Option<HashMap<u16, String>> {
-        let host = hostname.split_once(":").unwrap_or(("", "")).0;
+    // Accept every pending client of an edge-triggered listener and register it in epoll
+    fn accept_connections(&mut self, listener_fd: RawFd) {
+        loop {
+            let accepted = match self
+                .listeners
+                .iter()
+                .find(|(_, listener)| listener.as_raw_fd() == listener_fd)
+            {
+                Some((_, listener)) => listener.accept(),
+                None => return,
+            };
 
-        // Recherche par nom d'hôte
-        if let Some(server) = self
-            .servers
-            .values()
-            .find(|server| server.name.to_lowercase() == host)
-        {
-            return server.error_pages.clone();
+            match accepted {
+                Ok((stream, _addr)) => {
+                    if let Err(e) = self.register_connection(stream, listener_fd) {
+                        eprintln!("Error registering connection: {:?}", e);
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    eprintln!("Error accepting connection: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn register_connection(
+        &mut self,
+        stream: TcpStream,
+        listener_fd: RawFd,
+    ) -> std::io::Result<()> {
+        stream.set_nonblocking(true)?;
+        let stream_fd = stream.as_raw_fd();
+
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLRDHUP | libc::EPOLLET) as u32,
+            u64: stream_fd as u64,
+        };
+        let res =
+            unsafe { libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_ADD, stream_fd, &mut event) };
+        if res < 0 {
+            return Err(Error::last_os_error());
         }
 
-        // Recherche par descripteur de fichier (fd)
-        self.servers
-            .values()
-            .find(|server| server.listeners.contains(&fd))
-            .and_then(|server| server.error_pages.clone())
+        println!("\n*******************New Connection*******************",);
+        let (timeouts, max_requests) = self.get_connection_limits(listener_fd, "");
+        let mut connection = Connection::new(stream, listener_fd, timeouts, max_requests);
+        // Read before any Host header, so always those of the server owning the listener
+        connection.header_limits = self
+            .find_server(listener_fd, "")
+            .map(|server| server.header_limits)
+            .unwrap_or_default();
+        self.connections.insert(stream_fd, connection);
+        self.refresh_timer(stream_fd);
+        Ok(())
+    }
+
+    // Track the deadline matching the current state of the connection. A deadline
+    // pushed back (bytes received or sent) keeps the armed timer, which re-arms itself
+    // when it fires; only an earlier one needs a new timer.
+    fn refresh_timer(&mut self, stream_fd: RawFd) {
+        let connection = match self.connections.get_mut(&stream_fd) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        connection.timer = connection.deadline();
+        let at = match connection.timer {
+            Some((_, at)) if connection.timer_armed.is_none_or(|armed| at < armed) => at,
+            _ => return,
+        };
+
+        // Timers are not cancelled: a new token makes the previous one stale
+        self.next_timer_token += 1;
+        connection.timer_token = self.next_timer_token;
+        connection.timer_armed = Some(at);
+        self.timers.schedule(stream_fd, connection.timer_token, at);
     }
 
-    fn get_size_limit(&self, fd: RawFd, hostname: String) -> Option<usize> {
-        let host = hostname.split_once(":").unwrap_or(("", "")).0;
+    fn expire_timers(&mut self) {
+        let now = Instant::now();
+        for (stream_fd, token) in self.timers.expire(now) {
+            let kind = match self.connections.get_mut(&stream_fd) {
+                Some(connection) if connection.timer_token == token => {
+                    connection.timer_armed = None;
+                    match connection.timer {
+                        Some((kind, at)) if at <= now => kind,
+                        _ => {
+                            // The deadline moved since the timer was armed
+                            self.refresh_timer(stream_fd);
+                            continue;
+                        }
+                    }
+                }
+                _ => continue,
+            };
+            if let Err(e) = self.handle_timeout(stream_fd, kind) {
+                eprintln!("Error handling timeout: {:?}", e);
+                self.close_connection(stream_fd);
+            }
+        }
+    }
 
-        // Recherche par nom d'hôte
-        if let Some(server) = self
-            .servers
-            .values()
-            .find(|server| server.name.to_lowercase() == host)
-        {
-            return server.size_limit.clone();
+    // A client that stalls in the middle of a request gets a 408 before being
+    // disconnected; an idle or unresponsive one is simply disconnected
+    fn handle_timeout(&mut self, stream_fd: RawFd, kind: TimeoutKind) -> std::io::Result<()> {
+        let connection = match self.connections.get_mut(&stream_fd) {
+            Some(connection) => connection,
+            None => return Ok(()),
+        };
+
+        // The body read so far is already out of read_buf
+        let request_in_progress = !connection.read_buf.is_empty() || connection.pending.is_some();
+        match kind {
+            TimeoutKind::Header | TimeoutKind::Body if request_in_progress => {
+                println!("Request timeout on connection {}", stream_fd);
+                // The server the request names, if its head has arrived
+                let hostname = connection
+                    .pending
+                    .as_ref()
+                    .and_then(|(request, _)| request.headers.get("Host"))
+                    .unwrap_or_default();
+                let error_pages = find_server(&self.servers, connection.listener_fd, hostname)
+                    .and_then(Server::error_pages);
+                let response = HttpResponse::request_timeout(error_pages).with_keep_alive(None);
+                connection.read_buf.clear();
+                connection.pending = None;
+                connection.queue_response(response, true);
+                connection.phase = ConnectionPhase::Closing;
+                self.flush_connection(stream_fd)?;
+                self.refresh_timer(stream_fd);
+            }
+            _ => self.close_connection(stream_fd),
         }
+        Ok(())
+    }
 
-        // Recherche par descripteur de fichier (fd)
-        self.servers
-            .values()
-            .find(|server| server.listeners.contains(&fd))
-            .and_then(|server| server.size_limit.clone())
+    // Add or remove EPOLLOUT from the interest set of a connection
+    fn set_write_interest(&mut self, stream_fd: RawFd, enabled: bool) -> std::io::Result<()> {
+        let connection = match self.connections.get_mut(&stream_fd) {
+            Some(connection) => connection,
+            None => return Ok(()),
+        };
+        if connection.write_interest == enabled {
+            return Ok(());
+        }
+
+        let mut events = libc::EPOLLIN | libc::EPOLLRDHUP | libc::EPOLLET;
+        if enabled {
+            events |= libc::EPOLLOUT;
+        }
+        let mut event = libc::epoll_event {
+            events: events as u32,
+            u64: stream_fd as u64,
+        };
+        let res =
+            unsafe { libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_MOD, stream_fd, &mut event) };
+        if res < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        connection.write_interest = enabled;
+        Ok(())
+    }
+
+    fn close_connection(&mut self, stream_fd: RawFd) {
+        if let Some(connection) = self.connections.remove(&stream_fd) {
+            self.unregister_fd(stream_fd);
+            let _ = connection.stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    fn find_server(&self, fd: RawFd, hostname: &str) -> Option<&Server> {
+        find_server(&self.servers, fd, hostname)
+    }
+
+    fn router(&self, fd: RawFd, hostname: String) -> Option<Arc<Router>> {
+        self.find_server(fd, &hostname)
+            .map(|server| server.router.clone())
     }
 
-    fn process_request(&mut self, request: HttpRequest) -> HttpResponse {
+    fn get_error_pages(&self, fd: RawFd, hostname: String) -> Option<ErrorPages> {
+        self.find_server(fd, &hostname)
+            .and_then(Server::error_pages)
+    }
+
+    fn server_root(&self, fd: RawFd, hostname: &str) -> String {
+        self.find_server(fd, hostname)
+            .map_or(DEFAULT_ROOT, |server| server.root.as_str())
+            .to_string()
+    }
+
+    fn get_connection_limits(&self, fd: RawFd, hostname: &str) -> (Timeouts, usize) {
+        self.find_server(fd, hostname)
+            .map(|server| (server.timeouts, server.max_requests))
+            .unwrap_or((Timeouts::default(), DEFAULT_MAX_REQUESTS))
+    }
+
+    fn process_request(&mut self, mut request: HttpRequest) -> HttpResponse {
         let hostname = request
             .headers
             .get("Host")
             .map(|h| h.to_string())
             .unwrap_or_default();
 
-        let routes = Self::route_map(&self, request.listener_fd, hostname.clone());
-        let error_pages = Self::get_error_pages(&self, request.listener_fd, hostname.clone());
-        let size_limit = Self::get_size_limit(&self, request.listener_fd, hostname);
+        let router = Self::router(self, request.listener_fd, hostname.clone());
+        let error_pages = Self::get_error_pages(self, request.listener_fd, hostname.clone());
+        let server_root = self.server_root(request.listener_fd, &hostname);
+
+        // Route the path the files are resolved from, so that ".." can't skip a route
+        if request.path != "*" {
+            request.path = match normalize_path(&request.path) {
+                Ok(path) => path,
+                Err(err) => return HttpResponse::from_path_error(err, error_pages),
+            };
+        }
 
-        match routes.get(&request.path) {
-            Some(route_config) => HttpResponse::ok(request, route_config, error_pages, size_limit),
-            None => HttpResponse::get_static(request, error_pages),
+        let route = match &router {
+            Some(router) => router.route(&mut request.path, &mut request.query),
+            None => Ok(None),
+        };
+        match route {
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                HttpResponse::internal_server_error(error_pages)
+            }
+            Ok(Some(route)) => {
+                let root = route.config.document_root(route.prefix, &server_root);
+                request.params = route.params.clone();
+                HttpResponse::ok(request, &route, &root, error_pages)
+            }
+            Ok(None) => {
+                HttpResponse::get_static(request, &DocumentRoot::root(server_root), error_pages)
+            }
         }
     }
 
-    fn handle_connection(
-        &mut self,
-        stream: &mut TcpStream,
-        listener_fd: RawFd,
-    ) -> std::io::Result<()> {
-        let stream_fd = stream.as_raw_fd();
+    fn handle_event(&mut self, stream_fd: RawFd, flags: u32) -> std::io::Result<()> {
+        if flags & libc::EPOLLOUT as u32 != 0 {
+            self.handle_writable(stream_fd)?;
+        }
+        if flags & (libc::EPOLLIN | libc::EPOLLRDHUP) as u32 != 0 {
+            self.handle_readable(stream_fd)?;
+        }
+        Ok(())
+    }
 
-        // Create a new tail if it does not exist
-        if !self.request_queues.contains_key(&stream_fd) {
-            self.request_queues
-                .insert(stream_fd, RequestQueue::new(100)); // 100 est la taille max de la queue
+    // The socket accepts data again: drain the backlog, then resume reading
+    fn handle_writable(&mut self, stream_fd: RawFd) -> std::io::Result<()> {
+        if !self.flush_connection(stream_fd)? {
+            return Ok(());
         }
 
-        let mut keep_alive = true;
+        // Requests that arrived while we were blocked on output were left unread
+        match self.connections.get(&stream_fd) {
+            Some(connection) if connection.phase != ConnectionPhase::Closing => {
+                self.handle_readable(stream_fd)
+            }
+            _ => Ok(()),
+        }
+    }
 
-        println!("\n*******************New Connection*******************",);
+    // Read what the client sent, queue every complete request and answer them in order.
+    // Reading stops while a response waits to be sent, so a client that does not
+    // consume its responses cannot make us buffer more than what was already read.
+    fn handle_readable(&mut self, stream_fd: RawFd) -> std::io::Result<()> {
+        loop {
+            let connection = match self.connections.get_mut(&stream_fd) {
+                Some(connection) => connection,
+                None => return Ok(()),
+            };
 
-        while keep_alive {
-            stream.set_read_timeout(Some(std::time::Duration::from_millis(500)))?;
+            let can_read = !connection.requests.is_full()
+                && !connection.has_pending_output()
+                && !connection.read_closed
+                && connection.final_response.is_none()
+                && connection.phase != ConnectionPhase::Closing;
+            let buffered = connection.read_buf.len();
+            if can_read && !connection.fill_read_buf()? {
+                connection.read_closed = true;
+            }
+            let received = connection.read_buf.len() > buffered;
+
+            // Stop once nothing moves: the socket is drained, or the client has to
+            // read its responses first. Reads are bounded, so a full read buffer is
+            // parsed before reading on, and the head limits are checked on every read.
+            let parsed = self.parse_requests(stream_fd);
+            let answered = self.process_queue(stream_fd)?;
+            if !received && parsed == 0 && answered == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    // Move the complete requests of the read buffer to the request queue.
+    // Returns how many were queued.
+    fn parse_requests(&mut self, stream_fd: RawFd) -> usize {
+        let servers = &self.servers;
+        let connection = match self.connections.get_mut(&stream_fd) {
+            Some(connection) => connection,
+            None => return 0,
+        };
 
-            // Read the request and add it to the tail
-            match read_request(stream, listener_fd) {
-                Ok(request) => {
+        let mut parsed = 0;
+        while !connection.requests.is_full()
+            && connection.final_response.is_none()
+            && connection.phase != ConnectionPhase::Closing
+        {
+            match connection.next_request() {
+                ParseOutcome::Complete(request) => {
                     println!(
                         "-----------------New Request-----------------\n{:?}\n",
                         request
                     );
-                    keep_alive = check_connection_headers(&request);
-
-                    // Treat the request immediately instead of using a tail
-                    let mut requests_to_process = Vec::new();
-
-                    // Collect tail requests
-                    if let Some(queue) = self.request_queues.get_mut(&stream_fd) {
-                        if queue.push(request).is_ok() {
-                            while let Some(req) = queue.pop() {
-                                requests_to_process.push(req);
-                            }
-                        } else {
-                            let error_response = HttpResponse::service_unavailable(None);
-                            stream.write_all(&error_response.to_bytes())?;
-                            keep_alive = false;
-                            continue;
+                    connection.requests.push(request);
+                    parsed += 1;
+                }
+                ParseOutcome::Headers => {
+                    let (request, reader) = match &connection.pending {
+                        Some(pending) => pending,
+                        None => continue,
+                    };
+
+                    // The body is limited by the route, or else by the server, the
+                    // request is addressed to
+                    let hostname = request.headers.get("Host").unwrap_or_default();
+                    let server = find_server(servers, request.listener_fd, hostname);
+                    let mut path = normalize_path(&request.path).unwrap_or_default();
+                    let mut query = request.query.clone();
+                    let route = server.and_then(|server| {
+                        server.router.route(&mut path, &mut query).ok().flatten()
+                    });
+                    let route = route.as_ref().map(|route| route.config);
+                    connection.body_limit = route
+                        .and_then(|route| route.client_body_size_limit)
+                        .or(server.and_then(|server| server.size_limit));
+                    let error_pages = server.and_then(Server::error_pages);
+
+                    // An oversized body is refused before any of it is read; a chunked
+                    // one is checked as its chunks arrive
+                    let too_large = match (reader, connection.body_limit) {
+                        (BodyReader::Length(length), Some(limit)) => *length > limit,
+                        _ => false,
+                    };
+
+                    let rejection = if too_large {
+                        Some(HttpResponse::payload_too_large(error_pages))
+                    } else if expects_continue(request, reader) {
+                        // The client waits for our answer before sending the body:
+                        // refuse right away what would be refused after it
+                        let refusal = route.and_then(|route| {
+                            HttpResponse::check_method(route, &request.method, &error_pages).err()
+                        });
+                        if refusal.is_none() {
+                            connection.send_continue = true;
                         }
-                    }
-
-                    // Treat all requests collected
-                    for req in requests_to_process {
-                        let response = self.process_request(req);
-                        let final_response = add_connection_headers(response, keep_alive);
-                        stream.write_all(&final_response.to_bytes())?;
+                        refusal
+                    } else {
+                        None
+                    };
+
+                    if let Some(response) = rejection {
+                        connection.pending = None;
+                        connection.final_response = Some(response);
+                        break;
                     }
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    // println!("Timeout's over");
-                    keep_alive = false;
+                ParseOutcome::Incomplete => break,
+                error => {
+                    // The request may not name its server yet: the one owning the
+                    // listener answers
+                    let error_pages = find_server(servers, connection.listener_fd, "")
+                        .and_then(Server::error_pages);
+                    let response = match error {
+                        ParseOutcome::TooLarge => HttpResponse::payload_too_large(error_pages),
+                        ParseOutcome::UriTooLong => HttpResponse::uri_too_long(error_pages),
+                        ParseOutcome::HeadersTooLarge => {
+                            HttpResponse::request_header_fields_too_large(error_pages)
+                        }
+                        ParseOutcome::VersionNotSupported => {
+                            HttpResponse::http_version_not_supported(error_pages)
+                        }
+                        _ => HttpResponse::bad_request(error_pages),
+                    };
+                    connection.final_response = Some(response);
+                    break;
                 }
-                Err(e) => {
-                    eprintln!("Reading error of the request: {:?}", e);
-                    self.request_queues.remove(&stream_fd);
-                    return Err(e);
+            }
+        }
+        parsed
+    }
+
+    // Answer the queued requests in order, each one once the previous response
+    // has been sent. Returns how many were answered.
+    fn process_queue(&mut self, stream_fd: RawFd) -> std::io::Result<usize> {
+        let mut answered = 0;
+
+        loop {
+            let connection = match self.connections.get_mut(&stream_fd) {
+                Some(connection)
+                    if !connection.has_pending_output()
+                        && connection.phase != ConnectionPhase::Closing =>
+                {
+                    connection
+                }
+                _ => break,
+            };
+
+            let request = match connection.requests.pop() {
+                Some(request) => request,
+                None => {
+                    // Everything before it is answered: send the error that stopped parsing
+                    if let Some(response) = connection.final_response.take() {
+                        connection.queue_response(response.with_keep_alive(None), true);
+                        connection.phase = ConnectionPhase::Closing;
+                    } else if connection.send_continue {
+                        connection.send_continue = false;
+                        connection.queue_continue();
+                    } else if connection.read_closed {
+                        connection.phase = ConnectionPhase::Closing;
+                    }
+                    break;
                 }
+            };
+
+            self.answer_request(stream_fd, request);
+            answered += 1;
+
+            if !self.flush_connection(stream_fd)? {
+                return Ok(answered);
             }
         }
 
-        // Clean the tail when the connection is closed
-        self.request_queues.remove(&stream_fd);
-        Ok(())
+        self.flush_connection(stream_fd)?;
+        Ok(answered)
     }
-}
 
-fn read_request(stream: &mut TcpStream, listener_fd: RawFd) -> std::io::Result<HttpRequest> {
-    let mut buffer = Vec::new();
-    let mut temp_buffer = [0; 1024];
+    fn answer_request(&mut self, stream_fd: RawFd, request: HttpRequest) {
+        // The virtual host is known now: its limits apply from here on
+        let hostname = request.headers.get("Host").unwrap_or_default().to_string();
+        let (timeouts, max_requests) = self.get_connection_limits(request.listener_fd, &hostname);
+
+        // No more keep-alive once shutdown has started
+        let wants_keep_alive = check_connection_headers(&request) && self.drain_deadline.is_none();
+        let keep_alive = match self.connections.get_mut(&stream_fd) {
+            Some(connection) => {
+                connection.timeouts = timeouts;
+                connection.max_requests = max_requests;
+                connection.requests_served += 1;
+                connection.keep_alive_params().filter(|_| wants_keep_alive)
+            }
+            None => return,
+        };
 
-    // Initial reading for headers
-    let bytes_read = stream.read(&mut temp_buffer)?;
-    if bytes_read == 0 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::UnexpectedEof,
-            "Connection closed by peer",
-        ));
+        let chunked_allowed = request.is_http_1_1();
+        let listener_fd = request.listener_fd;
+        let response = self.process_request(request);
+
+        // A body of unknown size sent to an HTTP/1.0 client ends with the connection
+        let keep_alive = keep_alive.filter(|_| chunked_allowed || response.body.len().is_some());
+        let close = keep_alive.is_none();
+        let mut final_response = response.with_keep_alive(keep_alive);
+        if let Some(source_fd) = final_response.body.stream_fd() {
+            if let Err(e) = self.watch_stream(stream_fd, source_fd) {
+                eprintln!("Error watching response stream: {:?}", e);
+                let error_pages = self.get_error_pages(listener_fd, hostname);
+                final_response =
+                    HttpResponse::internal_server_error(error_pages).with_keep_alive(None);
+            }
+        }
+        if let Some(connection) = self.connections.get_mut(&stream_fd) {
+            connection.queue_response(final_response, chunked_allowed);
+            if close {
+                connection.phase = ConnectionPhase::Closing;
+            }
+        }
     }
-    buffer.extend_from_slice(&temp_buffer[..bytes_read]);
-
-    // Sprinkle the headers to obtain the length of the body
-    let new_buff = buffer.clone();
-    let request_raw = String::from_utf8_lossy(&new_buff);
-    let content_length = parse_content_length(&request_raw).unwrap_or(0);
 
-    // Continue reading if a body is expected
-    while buffer.len() < content_length + request_raw.find("\r\n\r\n").unwrap_or(0) + 4 {
-        let bytes_read = stream.read(&mut temp_buffer)?;
-        if bytes_read == 0 {
-            break;
+    // Wake the connection up whenever the source of its streamed body has data, or
+    // reaches its end. Edge-triggered, so data left unread while the socket is full
+    // doesn't wake us again; closing the source removes it from epoll.
+    fn watch_stream(&self, stream_fd: RawFd, source_fd: RawFd) -> std::io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLET) as u32,
+            u64: STREAM_EVENT | stream_fd as u64,
+        };
+        let res =
+            unsafe { libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_ADD, source_fd, &mut event) };
+        if res < 0 {
+            return Err(Error::last_os_error());
         }
-        buffer.extend_from_slice(&temp_buffer[..bytes_read]);
+        Ok(())
     }
 
-    match HttpRequest::from_raw(&buffer, listener_fd, stream.as_raw_fd()) {
-        Some(request) => Ok(request),
-        None => Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Invalid HTTP request",
-        )),
+    // Send pending output; arm EPOLLOUT if the kernel buffer is full and
+    // close the connection once its last response is out.
+    // Returns true when nothing is left to send and the connection is still open.
+    fn flush_connection(&mut self, stream_fd: RawFd) -> std::io::Result<bool> {
+        let (flushed, waiting, closing) = match self.connections.get_mut(&stream_fd) {
+            Some(connection) => (
+                connection.flush()?,
+                connection.waiting_for_body,
+                connection.phase == ConnectionPhase::Closing,
+            ),
+            None => return Ok(false),
+        };
+
+        if flushed && closing {
+            self.close_connection(stream_fd);
+            return Ok(false);
+        }
+
+        // A stream with no data ready wakes us through watch_stream
+        self.set_write_interest(stream_fd, !flushed && !waiting)?;
+        Ok(flushed)
     }
 }
 
-fn add_connection_headers(mut response: HttpResponse, keep_alive: bool) -> HttpResponse {
-    let connection_value = if keep_alive { "keep-alive" } else { "close" };
-    response
-        .headers
-        .push(("Connection".to_string(), connection_value.to_string()));
-
-    if keep_alive {
-        response
-            .headers
-            .push(("Keep-Alive".to_string(), "timeout=5, max=100".to_string()));
+// The server answering on a listener: the one named by the Host header,
+// or else the one owning the listener
+fn find_server<'a>(
+    servers: &'a HashMap<String, Server>,
+    fd: RawFd,
+    hostname: &str,
+) -> Option<&'a Server> {
+    let host = hostname.split(':').next().unwrap_or("").to_lowercase();
+
+    // Recherche par nom d'hôte
+    if let Some(server) = servers
+        .values()
+        .find(|server| server.name.to_lowercase() == host)
+    {
+        return Some(server);
     }
 
-    response
+    // Recherche par descripteur de fichier (fd)
+    servers
+        .values()
+        .find(|server| server.listeners.contains(&fd))
+}
+
+fn expects_continue(request: &HttpRequest, reader: &BodyReader) -> bool {
+    request.is_http_1_1()
+        && !matches!(reader, BodyReader::Length(0))
+        && request
+            .headers
+            .get("Expect")
+            .is_some_and(|expect| expect.trim().eq_ignore_ascii_case("100-continue"))
 }
 
 fn check_connection_headers(request: &HttpRequest) -> bool {
-    let connection_header = request.headers.get("Connection").map(|h| h.to_lowercase());
+    // Connection holds a list of options, e.g. "keep-alive, Upgrade"
+    let connection_header = request.headers.get_list("Connection").map(|h| {
+        h.split(',')
+            .map(|option| option.trim().to_lowercase())
+            .collect::<Vec<_>>()
+    });
 
     match connection_header {
-        Some(header) => {
-            if header == "close" {
+        Some(options) => {
+            if options.iter().any(|option| option == "close") {
                 false
-            } else if header == "keep-alive" {
+            } else if options.iter().any(|option| option == "keep-alive") {
                 true
             } else {
                 // Par défaut en HTTP/1.1, la connexion est keep-alive
@@ -362,13 +974,3 @@ fn check_connection_headers(request: &HttpRequest) -> bool {
         None => request.version == "HTTP/1.1", // Keep-alive par défaut en HTTP/1.1
     }
 }
-
-// Function to extract the length of the content of the HTTP headers
-fn parse_content_length(request: &str) -> Option<usize> {
-    for line in request.lines() {
-        if let Some(value) = line.strip_prefix("Content-Length: ") {
-            return value.trim().parse().ok();
-        }
-    }
-    None
-}
@@ -1,25 +1,35 @@
 use crate::http_request::HttpRequest;
-use crate::http_response::HttpResponse;
+use crate::http_response::{ErrorPages, HttpResponse};
+use crate::path_resolver::{resolve, DocumentRoot};
+use crate::response_body::ResponseBody;
 use multipart::server::Multipart;
-use std::collections::HashMap;
 use std::io::Cursor;
 use std::io::Read;
 use std::{
     fs::{self, File},
     io::Write,
-    path::Path,
 };
 
-pub fn handle_post(request: HttpRequest, error_page: Option<HashMap<u16, String>>) -> HttpResponse {
+pub fn handle_post(
+    request: HttpRequest,
+    root: &DocumentRoot,
+    error_page: Option<ErrorPages>,
+) -> HttpResponse {
+    // Uploaded files go to the "upload" directory of the document root
+    let upload_dir = match root.file("upload") {
+        Ok(dir) => dir,
+        Err(err) => return HttpResponse::from_path_error(err, error_page),
+    };
+
     if request.method == "GET" {
-        return HttpResponse::list_dir(request.path, error_page);
+        return HttpResponse::list_dir(request.path, &upload_dir, error_page);
     }
 
     let content_type = request
         .headers
         .get("Content-Type")
-        .unwrap_or(&"".to_string())
-        .clone();
+        .unwrap_or_default()
+        .to_string();
 
     if !content_type.starts_with("multipart/form-data;") {
         return HttpResponse::bad_request(error_page);
@@ -40,32 +50,34 @@ pub fn handle_post(request: HttpRequest, error_page: Option<HashMap<u16, String>
 
     while let Ok(Some(mut field)) = multipart.read_entry() {
         if let Some(file_name) = field.headers.filename.clone() {
-            let upload_dir = Path::new("./public/upload");
-
-            if let Err(err) = fs::create_dir_all(upload_dir) {
+            if let Err(err) = fs::create_dir_all(&upload_dir) {
                 eprintln!("Failed to create upload directory: {}", err);
                 return HttpResponse::internal_server_error(error_page);
             }
 
-            let save_path = upload_dir.join(file_name);
+            // The filename comes from the client: keep it inside the upload directory
+            let save_path = match resolve(&upload_dir, &file_name) {
+                Ok(path) => path,
+                Err(err) => return HttpResponse::from_path_error(err, error_page),
+            };
 
             let mut file = match File::create(save_path) {
                 Ok(f) => f,
                 Err(_) => return HttpResponse::bad_request(error_page),
             };
             let mut buffer = Vec::new();
-            if let Err(_) = field.data.read_to_end(&mut buffer) {
+            if field.data.read_to_end(&mut buffer).is_err() {
                 return HttpResponse::bad_request(error_page);
             };
 
-            if let Err(_) = file.write_all(&buffer){
+            if file.write_all(&buffer).is_err() {
                 return HttpResponse::bad_request(error_page);
             };
 
             return HttpResponse {
                 status_code: 303,
-                headers: vec![("Location".to_string(), "/upload".to_string())],
-                body: Vec::new().into(),
+                headers: vec![("Location".to_string(), "/upload".to_string())].into(),
+                body: ResponseBody::empty(),
             };
         }
     }
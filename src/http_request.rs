@@ -1,11 +1,23 @@
 use std::{collections::HashMap, os::fd::RawFd};
 
+use crate::headers::Headers;
+use crate::path_resolver::percent_decode;
+
+// Why the head of a request was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestError {
+    BadRequest,          // Malformed request line or header (400)
+    VersionNotSupported, // Well-formed, but neither HTTP/1.0 nor HTTP/1.1 (505)
+}
+
 #[derive(Debug)]
 pub struct HttpRequest {
     pub method: String,
-    pub path: String,
+    pub path: String,          // Percent-decoded, without the query
+    pub query: Option<String>, // Raw query string, after the '?'
+    pub params: Vec<(String, String)>, // Set by the route: ":name" and "*name" values, regex groups
     pub version: String,
-    pub headers: HashMap<String, String>,
+    pub headers: Headers,
     pub body: Vec<u8>,
     pub stream_fd: RawFd,
     pub listener_fd: RawFd,
@@ -21,63 +33,123 @@ impl HttpRequest {
             // In HTTP/1.1, the connection is keep-alive by default
             self.headers
                 .get("Connection")
-                .map_or(true, |v| v.to_lowercase() != "close")
+                .is_none_or(|v| v.to_lowercase() != "close")
         } else {
             // In HTTP/1.0, the connection is closed by default
             self.headers
                 .get("Connection")
-                .map_or(false, |v| v.to_lowercase() == "keep-alive")
+                .is_some_and(|v| v.to_lowercase() == "keep-alive")
         }
     }
 
-    // Sprinkle a raw http request from bytes.
-    pub fn from_raw(raw_request: &[u8], listener_fd: RawFd, stream_fd: RawFd) -> Option<Self> {
-        // Find the end of the headers (separated by "\r\n\r\n")
-        let headers_end = raw_request
-            .windows(4)
-            .position(|window| window == b"\r\n\r\n")?;
-        let (header_bytes, body_bytes) = raw_request.split_at(headers_end + 4);
-
-        // Convert the headers to a UTF-8 chain (necessary for the HTTP structure)
-        let headers_str = std::str::from_utf8(&header_bytes).ok()?;
-
-        let mut lines = headers_str.split("\r\n");
+    // Parse the head of a request: the request line and the header lines, up to
+    // and including the empty line that ends them (RFC 9112, sections 3 and 5).
+    // The body is read separately and starts empty.
+    pub fn parse_head(
+        head: &[u8],
+        listener_fd: RawFd,
+        stream_fd: RawFd,
+    ) -> Result<Self, RequestError> {
+        let head = head
+            .strip_suffix(b"\r\n\r\n")
+            .ok_or(RequestError::BadRequest)?;
+        let mut lines = split_crlf(head).into_iter();
 
-        // Sprinkle the request line
-        let request_line = lines.next()?;
-        let mut parts = request_line.split_whitespace();
-        let method = parts.next()?.to_string();
-        let path = parts.next()?.to_string();
-        let version = parts.next()?.to_string();
+        // Sprinkle the request line: method SP request-target SP HTTP-version
+        let request_line = lines.next().unwrap_or_default();
+        let parts: Vec<&[u8]> = request_line.split(|&b| b == b' ').collect();
+        let [method, target, version] = parts[..] else {
+            return Err(RequestError::BadRequest);
+        };
+        if method.is_empty() || !method.iter().all(|&b| is_tchar(b)) {
+            return Err(RequestError::BadRequest);
+        }
+        if target.is_empty() || target.iter().any(|&b| b <= b' ' || b == 0x7f) {
+            return Err(RequestError::BadRequest);
+        }
+        let version = parse_version(version)?;
+        let (path, query) = split_target(&String::from_utf8_lossy(target))?;
 
-        // Sprinkle the headers
-        let mut headers = HashMap::new();
+        // Sprinkle the headers: field-name ":" OWS field-value OWS
+        let mut headers = Headers::new();
         for line in lines {
-            if line.is_empty() {
-                break;
+            // Line folding is obsolete and must be rejected in requests
+            if line.starts_with(b" ") || line.starts_with(b"\t") {
+                return Err(RequestError::BadRequest);
             }
-            if let Some((key, value)) = line.split_once(": ") {
-                headers.insert(key.to_string(), value.to_string());
+            let colon = line
+                .iter()
+                .position(|&b| b == b':')
+                .ok_or(RequestError::BadRequest)?;
+            let (name, value) = (&line[..colon], trim_ows(&line[colon + 1..]));
+
+            // No whitespace is allowed between the name and the colon
+            if name.is_empty() || !name.iter().all(|&b| is_tchar(b)) {
+                return Err(RequestError::BadRequest);
             }
+            if value.iter().any(|&b| (b < b' ' && b != b'\t') || b == 0x7f) {
+                return Err(RequestError::BadRequest);
+            }
+
+            headers.append(
+                String::from_utf8_lossy(name).into_owned(),
+                String::from_utf8_lossy(value).into_owned(),
+            );
         }
 
-        // The body is already isolated in the form of bytes
-        let body = body_bytes.to_vec();
+        // HTTP/1.1 requests must name their host exactly once
+        let host_count = headers.get_all("Host").count();
+        if host_count > 1 || (version == "HTTP/1.1" && host_count == 0) {
+            return Err(RequestError::BadRequest);
+        }
 
-        Some(Self {
-            method,
+        Ok(Self {
+            method: String::from_utf8_lossy(method).into_owned(),
             path,
+            query,
+            params: Vec::new(),
             version,
             headers,
-            body,
+            body: Vec::new(),
             listener_fd,
             stream_fd,
         })
     }
 
+    // Value of a parameter of the route, e.g. "id" for "/users/:id/avatar"
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Decoded name/value pairs of the query string, in order
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        self.query.as_deref().map(parse_urlencoded).unwrap_or_default()
+    }
+
+    // First value of a query parameter
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query_params()
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    // Fields of an application/x-www-form-urlencoded body; None for any other content type
+    pub fn form_params(&self) -> Option<Vec<(String, String)>> {
+        let content_type = self.headers.get("Content-Type")?;
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        if !media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return None;
+        }
+        Some(parse_urlencoded(&String::from_utf8_lossy(&self.body)))
+    }
+
     pub fn get_cookies(&self) -> HashMap<String, String> {
         let mut cookies = HashMap::new();
-        if let Some(cookie_header) = self.headers.get("Cookie") {
+        for cookie_header in self.headers.get_all("Cookie") {
             // Parse cookie header
             for cookie in cookie_header.split(';') {
                 if let Some((name, value)) = cookie.split_once('=') {
@@ -88,3 +160,90 @@ impl HttpRequest {
         cookies
     }
 }
+
+// Split a request target into its decoded path and its raw query (RFC 9112, section 3.2).
+// The absolute form sent to proxies is reduced to the same path and query.
+fn split_target(target: &str) -> Result<(String, Option<String>), RequestError> {
+    if target == "*" {
+        return Ok((target.to_string(), None));
+    }
+    let (target, absolute) = match target.split_once("://") {
+        Some((scheme, rest))
+            if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") =>
+        {
+            // Skip the authority, the path may be empty
+            let start = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+            (&rest[start..], true)
+        }
+        _ => (target, false),
+    };
+    // A fragment is never sent, drop it if a client does
+    let target = target.split('#').next().unwrap_or_default();
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    };
+    let path = match path {
+        "" if absolute => "/",
+        path if path.starts_with('/') => path,
+        _ => return Err(RequestError::BadRequest),
+    };
+    let path = percent_decode(path).map_err(|_| RequestError::BadRequest)?;
+    Ok((path, query))
+}
+
+// Decode "a=1&b=x+y" into ordered pairs; '+' stands for a space, a name without '=' gets
+// an empty value. Invalid UTF-8 in an escape is replaced rather than refused.
+pub fn parse_urlencoded(input: &str) -> Vec<(String, String)> {
+    let decode = |part: &str| {
+        let part = part.replace('+', " ");
+        String::from_utf8_lossy(&urlencoding::decode_binary(part.as_bytes())).into_owned()
+    };
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+// Split on CRLF; a bare CR or LF is left in the line and refused as a control character
+fn split_crlf(mut input: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    while let Some(pos) = input.windows(2).position(|window| window == b"\r\n") {
+        lines.push(&input[..pos]);
+        input = &input[pos + 2..];
+    }
+    lines.push(input);
+    lines
+}
+
+// Characters allowed in a method or a header name (RFC 9110, section 5.6.2)
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn trim_ows(value: &[u8]) -> &[u8] {
+    let is_ows = |b: &u8| *b == b' ' || *b == b'\t';
+    let start = value.iter().position(|b| !is_ows(b)).unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|b| !is_ows(b))
+        .map_or(start, |end| end + 1);
+    &value[start..end]
+}
+
+// HTTP-version is "HTTP/" DIGIT "." DIGIT; we speak 1.0 and 1.1
+fn parse_version(version: &[u8]) -> Result<String, RequestError> {
+    match version {
+        b"HTTP/1.1" | b"HTTP/1.0" => Ok(String::from_utf8_lossy(version).into_owned()),
+        [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+            if major.is_ascii_digit() && minor.is_ascii_digit() =>
+        {
+            Err(RequestError::VersionNotSupported)
+        }
+        _ => Err(RequestError::BadRequest),
+    }
+}
@@ -1,26 +1,50 @@
 use std::{
     collections::HashMap,
     fs::{self},
-    path::Path,
+    path::{Path, PathBuf},
     time::SystemTime,
 };
 
-use urlencoding::decode;
-
 use crate::{
-    cgi::handle_route, config::RouteConfig, delete_file::handle_delete, file_upload::handle_post,
-    http_request::HttpRequest, session::Session,
+    cgi::{handle_redirection, handle_route, handle_route_file},
+    cgi_handler::CgiOutput,
+    config::RouteConfig,
+    delete_file::handle_delete,
+    file_upload::handle_post,
+    headers::Headers,
+    http_request::HttpRequest,
+    path_resolver::{
+        percent_encode_path, resolve, DocumentRoot, PathError, DEFAULT_INDEX, DEFAULT_ROOT,
+    },
+    response_body::{Framing, ResponseBody},
+    router::RouteMatch,
+    session::Session,
 };
+
+// Used when the root of a server has no error.html or list_dir.html
+const DEFAULT_ERROR_TEMPLATE: &str = "<!DOCTYPE html>\n<html>\n<head><title>Error {{status_code}}</title></head>\n\
+    <body>\n<h1>Error {{status_code}}</h1>\n<p>{{message}}</p>\n</body>\n</html>\n";
+const DEFAULT_LIST_TEMPLATE: &str =
+    "<!DOCTYPE html>\n<html>\n<head><title>Index</title></head>\n<body>\n{{content}}\n</body>\n</html>\n";
+
+// The error pages of a server: its custom pages by status, relative to its root, which
+// also holds the templates of the error and listing pages (error.html, list_dir.html)
+#[derive(Debug, Clone)]
+pub struct ErrorPages {
+    pub root: PathBuf,
+    pub pages: HashMap<u16, String>,
+}
+
 #[derive(Debug)]
 pub struct HttpResponse {
     pub status_code: u16,
-    pub headers: Vec<(String, String)>,
-    pub body: Vec<u8>,
+    pub headers: Headers,
+    pub body: ResponseBody,
 }
 
 impl HttpResponse {
     // Create a new http_response
-    pub fn new(status_code: u16, headers: Vec<(String, String)>, body: Vec<u8>) -> Self {
+    pub fn new(status_code: u16, headers: Headers, body: ResponseBody) -> Self {
         Self {
             status_code,
             headers,
@@ -28,14 +52,23 @@ impl HttpResponse {
         }
     }
 
-    pub fn with_keep_alive(mut self, keep_alive: bool) -> Self {
-        let connection_value = if keep_alive { "keep-alive" } else { "close" };
-        self.headers
-            .push(("Connection".to_string(), connection_value.to_string()));
-
-        if keep_alive {
-            self.headers
-                .push(("Keep-Alive".to_string(), "timeout=5, max=100".to_string()));
+    // Set the Connection header; `keep_alive` holds the idle timeout in seconds
+    // and the number of requests the connection still accepts, None to close it
+    pub fn with_keep_alive(mut self, keep_alive: Option<(u64, usize)>) -> Self {
+        let connection_value = if keep_alive.is_some() {
+            "keep-alive"
+        } else {
+            "close"
+        };
+        self.headers.insert("Connection", connection_value);
+
+        match keep_alive {
+            Some((timeout, max)) => self
+                .headers
+                .insert("Keep-Alive", format!("timeout={}, max={}", timeout, max)),
+            None => {
+                self.headers.remove("Keep-Alive");
+            }
         }
 
         self
@@ -52,171 +85,289 @@ impl HttpResponse {
             Some(exp) => format!("{}={}; Expires={:?}", name, value, exp),
             None => format!("{}={}", name, value),
         };
-        self.headers.push(("Set-Cookie".to_string(), cookie));
-    }
-
-    pub fn get_static(request: HttpRequest, error_page: Option<HashMap<u16, String>>) -> Self {
-        if let Some((mime_type, content)) = Self::serve_static_file(&request.path) {
-            return Self {
-                status_code: 200,
-                headers: vec![
-                    ("Content-Type".to_string(), mime_type),
-                    ("Content-Length".to_string(), content.len().to_string()),
-                ],
-                body: content,
-            };
-        }
+        self.headers.append("Set-Cookie", cookie);
+    }
 
-        println!("Not found (get_static)");
-        Self::not_found(error_page)
+    pub fn get_static(
+        request: HttpRequest,
+        root: &DocumentRoot,
+        error_page: Option<ErrorPages>,
+    ) -> Self {
+        Self::serve_files(request, &RouteConfig::default(), root, error_page)
     }
 
-    // Generate a ok_response (200 OK)
-    pub fn ok(
+    // The file of a request under the document root of its route. With `try_files`,
+    // the entries are tried in order ("$uri" standing for the request path) and the
+    // last one is the fallback: a path served from the same root, or "=404" for a status.
+    pub fn serve_files(
         request: HttpRequest,
-        route_config: &RouteConfig,
-        error_page: Option<HashMap<u16, String>>,
-        size_limit: Option<usize>,
+        route: &RouteConfig,
+        root: &DocumentRoot,
+        error_page: Option<ErrorPages>,
     ) -> Self {
-        let methodes = match route_config.accepted_methods.clone() {
-            Some(methode) => methode,
-            None => return Self::bad_request(error_page),
+        let Some((fallback, entries)) = route.try_files.as_deref().and_then(|t| t.split_last())
+        else {
+            return Self::serve_path(&request.path, &request, route, root, error_page.clone())
+                .unwrap_or_else(|| Self::not_found(error_page));
         };
 
-        if !methodes.contains(&request.method) {
-            return Self::method_not_allowed(error_page);
+        for entry in entries {
+            let path = entry.replace("$uri", &request.path);
+            if let Some(response) =
+                Self::serve_path(&path, &request, route, root, error_page.clone())
+            {
+                return response;
+            }
         }
 
-        match request.path.as_str() {
-            "/upload" => Self::handle_post_response(request, error_page, size_limit),
-            "/delete" => handle_delete(request, error_page),
-            _ => handle_route(route_config, request, error_page),
+        if let Some(status) = fallback.strip_prefix('=') {
+            let status_code = status.parse().unwrap_or(404);
+            return Self::error_template(status_code, reason_phrase(status_code), error_page);
         }
+        let path = fallback.replace("$uri", &request.path);
+        Self::serve_path(&path, &request, route, root, error_page.clone())
+            .unwrap_or_else(|| Self::not_found(error_page))
     }
 
-    pub fn from_cgi_output(
-        output: (Vec<u8>, Vec<u8>),
-        error_page: Option<HashMap<u16, String>>,
-    ) -> Self {
-        let (stdout, stderr) = output;
-        if !stderr.is_empty() {
-            match String::from_utf8(stderr) {
-                Ok(body) => HttpResponse {
-                    status_code: 200,
-                    headers: vec![("Content-Type".to_string(), "text/html".to_string())],
-                    body: body.into(),
-                },
-                Err(_) => HttpResponse::internal_server_error(error_page),
+    // The file at a decoded path, or None if there is none. A directory is redirected
+    // to its path with a trailing slash, so that relative links in its pages work, then
+    // served by its first `index` file, or by its listing if the route allows it.
+    fn serve_path(
+        path: &str,
+        request: &HttpRequest,
+        route: &RouteConfig,
+        root: &DocumentRoot,
+        error_page: Option<ErrorPages>,
+    ) -> Option<Self> {
+        let file_path = match root.resolve(path) {
+            Ok(file_path) => file_path,
+            Err(err) => return Some(Self::from_path_error(err, error_page)),
+        };
+
+        if file_path.is_dir() {
+            if !path.ends_with('/') {
+                let mut location = percent_encode_path(&format!("{}/", path));
+                if let Some(query) = &request.query {
+                    location.push('?');
+                    location.push_str(query);
+                }
+                return Some(Self::redirect(301, &location));
             }
-        } else {
-            match String::from_utf8(stdout) {
-                Ok(body) => HttpResponse {
-                    status_code: 200,
-                    headers: vec![("Content-Type".to_string(), "text/html".to_string())],
-                    body: body.into(),
-                },
-                Err(_) => HttpResponse::internal_server_error(error_page),
+
+            let default_index = [DEFAULT_INDEX.to_string()];
+            for name in route.index.as_deref().unwrap_or(&default_index) {
+                let index_path = match root.resolve(&format!("{}{}", path, name)) {
+                    Ok(index_path) => index_path,
+                    Err(err) => return Some(Self::from_path_error(err, error_page)),
+                };
+                if let Some(response) = Self::static_file(&index_path) {
+                    return Some(response);
+                }
             }
+
+            if route.directory_listing == Some(true) {
+                return Some(Self::list_dir(path.to_string(), &file_path, error_page));
+            }
+            return None;
+        }
+
+        Self::static_file(&file_path)
+    }
+
+    // 200 response with the content of a regular file
+    fn static_file(file_path: &Path) -> Option<Self> {
+        let (mime_type, body) = Self::serve_static_file(file_path)?;
+        Some(Self {
+            status_code: 200,
+            headers: vec![("Content-Type".to_string(), mime_type)].into(),
+            body,
+        })
+    }
+
+    // The error answering a method the route does not accept. A route without
+    // accepted_methods accepts none.
+    pub fn check_method(
+        route: &RouteConfig,
+        method: &str,
+        error_page: &Option<ErrorPages>,
+    ) -> Result<(), Self> {
+        match &route.accepted_methods {
+            None => Err(Self::bad_request(error_page.clone())),
+            Some(methods) if !methods.iter().any(|accepted| accepted == method) => {
+                Err(Self::method_not_allowed(error_page.clone()))
+            }
+            Some(_) => Ok(()),
         }
     }
 
-    pub fn handle_post_response(
+    // Generate a ok_response (200 OK)
+    pub fn ok(
         request: HttpRequest,
-        error_page: Option<HashMap<u16, String>>,
-        size_limit: Option<usize>,
+        route: &RouteMatch,
+        root: &DocumentRoot,
+        error_page: Option<ErrorPages>,
     ) -> Self {
-        if request.body.len() > size_limit.unwrap_or(0) {
-            return Self::payload_too_large(error_page);
+        let route_config = route.config;
+        if let Err(response) = Self::check_method(route_config, &request.method, &error_page) {
+            return response;
+        }
+
+        if let Some(response) = handle_redirection(route, &request) {
+            return response;
+        }
+
+        // A file below the route, or matched by a regex route
+        if !route.own_path {
+            return handle_route_file(route_config, request, root, error_page);
+        }
+
+        match route.prefix {
+            // The body size limit was enforced while the request was read
+            "/upload" => handle_post(request, root, error_page),
+            "/delete" => handle_delete(request, root, error_page),
+            _ => handle_route(route_config, request, root, error_page),
+        }
+    }
+
+    // A client-supplied path that could not be resolved under its root
+    pub fn from_path_error(err: PathError, error_page: Option<ErrorPages>) -> Self {
+        match err {
+            PathError::Invalid => Self::bad_request(error_page),
+            PathError::Forbidden => Self::forbidden(error_page),
+            PathError::NotFound => Self::not_found(error_page),
+        }
+    }
+
+    // Redirect to `location`, with a short page for clients that don't follow it
+    pub fn redirect(status_code: u16, location: &str) -> Self {
+        let mut response = Self {
+            status_code,
+            headers: Headers::new(),
+            body: ResponseBody::empty(),
         };
+        let title = format!("{} {}", status_code, reason_phrase(status_code));
+        let href = html_escape(location);
+        let body = format!(
+            "<!DOCTYPE html>\n<html>\n<head><title>{title}</title></head>\n<body>\n\
+             <h1>{title}</h1>\n<p>Redirecting to <a href=\"{href}\">{href}</a>.</p>\n\
+             </body>\n</html>\n"
+        );
+        response.headers.insert("Location", location);
+        response.headers.insert("Content-Type", "text/html");
+        response.body = body.into();
+        response
+    }
 
-        handle_post(request, error_page)
+    // The output of a CGI script, sent as the script writes it
+    pub fn from_cgi_output(output: CgiOutput) -> Self {
+        HttpResponse {
+            status_code: 200,
+            headers: vec![("Content-Type".to_string(), "text/html".to_string())].into(),
+            body: ResponseBody::Stream(Box::new(output)),
+        }
     }
 
     // Generate a bad_request_response (400 Bad Request)
-    pub fn bad_request(error_page: Option<HashMap<u16, String>>) -> Self {
+    pub fn bad_request(error_page: Option<ErrorPages>) -> Self {
         Self::error_template(400, "Bad Request", error_page)
     }
 
     // Generate a forbidden_response (403 Forbidden)
     //  The server understood the request, but refuses to execute it because of a lack of permissions.
-    pub fn forbidden(error_page: Option<HashMap<u16, String>>) -> Self {
+    pub fn forbidden(error_page: Option<ErrorPages>) -> Self {
         Self::error_template(403, "Forbidden", error_page)
     }
 
     // Generate a not_found_response (404 Not Found)
-    pub fn not_found(error_page: Option<HashMap<u16, String>>) -> Self {
+    pub fn not_found(error_page: Option<ErrorPages>) -> Self {
         Self::error_template(404, "Not Found", error_page)
     }
 
+    // Generate a request_timeout_response (408 Request Timeout)
+    // The client did not finish sending its request in time.
+    pub fn request_timeout(error_page: Option<ErrorPages>) -> Self {
+        Self::error_template(408, "Request Timeout", error_page)
+    }
+
+    // Generate a uri_too_long_response (414 URI Too Long)
+    pub fn uri_too_long(error_page: Option<ErrorPages>) -> Self {
+        Self::error_template(414, "URI Too Long", error_page)
+    }
+
+    // Generate a request_header_fields_too_large_response (431 Request Header Fields Too Large)
+    pub fn request_header_fields_too_large(error_page: Option<ErrorPages>) -> Self {
+        Self::error_template(431, "Request Header Fields Too Large", error_page)
+    }
+
+    // Generate a http_version_not_supported_response (505 HTTP Version Not Supported)
+    pub fn http_version_not_supported(error_page: Option<ErrorPages>) -> Self {
+        Self::error_template(505, "HTTP Version Not Supported", error_page)
+    }
+
     // Generate a method_not_allowed_response (405 Method Not Allowed)
     //  The HTTP method used (Get, Post, Put, Delete, etc.) is not allowed for this resource.
-    pub fn method_not_allowed(error_page: Option<HashMap<u16, String>>) -> Self {
+    pub fn method_not_allowed(error_page: Option<ErrorPages>) -> Self {
         Self::error_template(405, "Method Not Allowed", error_page)
     }
 
     // Generate a service_unavailable_response (503 Service Unavailable)
-    pub fn service_unavailable(error_page: Option<HashMap<u16, String>>) -> Self {
+    pub fn service_unavailable(error_page: Option<ErrorPages>) -> Self {
         Self::error_template(503, "Service Unavailable", error_page)
     }
 
     // Generate a payload_too_large_response (413 Payload Too Large)
     // The size of the request body exceeds the limits accepted by the server.
-    pub fn payload_too_large(error_page: Option<HashMap<u16, String>>) -> Self {
+    pub fn payload_too_large(error_page: Option<ErrorPages>) -> Self {
         Self::error_template(413, "Payload Too Large", error_page)
     }
 
     // Generate a internal_server_error_response (500 Internal Server Error)
     // A generic error when the server encounters an unexpected problem.
-    pub fn internal_server_error(error_page: Option<HashMap<u16, String>>) -> Self {
+    pub fn internal_server_error(error_page: Option<ErrorPages>) -> Self {
         Self::error_template(500, "Internal Server Error", error_page)
     }
 
     fn error_template(
         status_code: u16,
         message: &str,
-        error_page: Option<HashMap<u16, String>>,
+        error_page: Option<ErrorPages>,
     ) -> Self {
-        if let Some(custom_routes) = error_page.clone() {
-            // println!("error_page: {:?}", error_page);
-            if let Some(custom_path) = custom_routes.get(&status_code) {
-                println!("custom_path: {:?}", custom_path);
-                let good_path = &format!(".{}", custom_path);
-                let path = Path::new(good_path);
-                if path.exists() {
-                    return Self::page_server(status_code, &custom_path, error_page);
-                }
-            }
-        }
-
-        // Read the HTML file
-        let template = match fs::read_to_string("./public/error.html") {
-            Ok(temp) => temp,
-            Err(_) => return Self::internal_server_error(error_page),
+        let root = pages_root(&error_page);
+
+        // The custom page of the server for this status, else the template filled in
+        let custom_page = error_page
+            .as_ref()
+            .and_then(|error_page| error_page.pages.get(&status_code))
+            .and_then(|custom_path| resolve(root, custom_path).ok())
+            .and_then(|page| fs::read_to_string(page).ok());
+        let body = match custom_page {
+            Some(page) => page,
+            None => template(root, "error.html", DEFAULT_ERROR_TEMPLATE)
+                .replace("{{status_code}}", &status_code.to_string())
+                .replace("{{message}}", message),
         };
 
-        // Replace the reserved spaces
-        let body = template
-            .replace("{{status_code}}", &status_code.to_string())
-            .replace("{{message}}", message);
-
         // Create the HTTP response
         Self {
             status_code,
             headers: vec![
                 ("Content-Type".to_string(), "text/html".to_string()),
                 ("Content-Length".to_string(), body.len().to_string()),
-            ],
-            body: body.into_bytes(),
+            ]
+            .into(),
+            body: body.into(),
         }
     }
 
-    pub fn list_dir(dir: String, error_page: Option<HashMap<u16, String>>) -> Self {
-        let template = match fs::read_to_string("./public/list_dir.html") {
-            Ok(temp) => temp,
-            Err(_) => return Self::internal_server_error(error_page),
-        };
+    // Listing of the directory `dir_path`, reached at the URL path `dir`
+    pub fn list_dir(
+        dir: String,
+        dir_path: &Path,
+        error_page: Option<ErrorPages>,
+    ) -> Self {
+        let template = template(pages_root(&error_page), "list_dir.html", DEFAULT_LIST_TEMPLATE);
 
-        let content = Self::list_content(dir);
+        let content = Self::list_content(dir, dir_path);
         if content == "!existe" {
             return Self::internal_server_error(error_page);
         }
@@ -254,15 +405,15 @@ impl HttpResponse {
             headers: vec![
                 ("Content-Type".to_string(), "text/html".to_string()),
                 ("Content-Length".to_string(), body.len().to_string()),
-            ],
-            body: body.into_bytes(),
+            ]
+            .into(),
+            body: body.into(),
         }
     }
 
-    fn list_content(dir: String) -> String {
-        let path_str = format!("./public{}", dir);
+    fn list_content(dir: String, dir_path: &Path) -> String {
         println!("dir: {}", dir);
-        let cont = match fs::read_dir(path_str) {
+        let cont = match fs::read_dir(dir_path) {
             Ok(entries) => {
                 let mut content = String::new();
                 for entry in entries {
@@ -279,7 +430,7 @@ impl HttpResponse {
                     };
 
                     let mut buton = String::new();
-                    if dir == "/upload" {
+                    if dir.trim_end_matches('/') == "/upload" {
                         buton = format!(
                             "<button type=\"button\" class=\"delete-btn\" onclick=\"deleteFile('{}')\">
                              <i class=\"fas fa-trash-alt\"></i> Delete
@@ -297,7 +448,7 @@ impl HttpResponse {
                                 <span[Folder]  class=\"file-name\" > <a class=\"file-link\" href=\"{}/{}\">{}</a>{}</span>
                             </div>
                             ",
-                            dir.trim_start_matches("/"),
+                            dir.trim_end_matches('/'),
                             file_name,
                             file_name,
                             buton
@@ -315,7 +466,7 @@ impl HttpResponse {
                                     {}
                                 </div>
                             </li>",
-                            dir.trim_start_matches("/"),
+                            dir.trim_end_matches('/'),
                             file_name,
                             file_name,
                             buton
@@ -331,10 +482,9 @@ impl HttpResponse {
 
     pub fn page_server(
         status_code: u16,
-        path: &str,
-        error_page: Option<HashMap<u16, String>>,
+        real_path: &Path,
+        error_page: Option<ErrorPages>,
     ) -> Self {
-        let real_path = format!("./public/{}", path);
         let body = match fs::read_to_string(real_path) {
             Ok(temp) => temp,
             Err(_) => return Self::internal_server_error(error_page),
@@ -345,58 +495,58 @@ impl HttpResponse {
             headers: vec![
                 ("Content-Type".to_string(), "text/html".to_string()),
                 ("Content-Length".to_string(), body.len().to_string()),
-            ],
-            body: body.into_bytes(),
+            ]
+            .into(),
+            body: body.into(),
         }
     }
 
-    // Generate the http_response structure to a good format to send
-    pub fn to_bytes(&self) -> Vec<u8> {
+    // Split the response into the bytes of its status line and headers, and its body.
+    // The framing is chosen from the body: Content-Length when its size is known,
+    // chunked otherwise (or end of connection for clients that can't decode chunks).
+    pub fn into_parts(mut self, chunked_allowed: bool) -> (Vec<u8>, ResponseBody, Framing) {
+        self.headers.remove("Content-Length");
+        self.headers.remove("Transfer-Encoding");
+
+        let framing = match self.body.len() {
+            Some(len) => {
+                self.headers.insert("Content-Length", len.to_string());
+                Framing::ContentLength
+            }
+            None if chunked_allowed => {
+                self.headers.insert("Transfer-Encoding", "chunked");
+                Framing::Chunked
+            }
+            None => Framing::Close,
+        };
+
+        (self.head_bytes(), self.body, framing)
+    }
+
+    // Generate the status line and headers to a good format to send
+    pub fn head_bytes(&self) -> Vec<u8> {
         let headers = self
             .headers
             .iter()
             .map(|(k, v)| format!("{}: {}\r\n", k, v))
             .collect::<String>();
 
-        let response_text = format!(
+        format!(
             "HTTP/1.1 {} {}\r\n{}\r\n",
             self.status_code,
-            self.reason_phrase(),
+            reason_phrase(self.status_code),
             headers
-        );
-
-        let mut response_bytes = response_text.into_bytes();
-
-        response_bytes.extend_from_slice(&self.body);
-
-        response_bytes
-    }
-
-    // Message corresponding to each response's status
-    fn reason_phrase(&self) -> &str {
-        match self.status_code {
-            200 => "OK",
-            400 => "Bad Request",
-            403 => "Forbidden",
-            404 => "Not Found",
-            405 => "Method Not Allowed",
-            413 => "Payload Too Large",
-            500 => "Internal Server Error",
-            503 => "Service Unavailable",
-            _ => "Unknown",
-        }
+        )
+        .into_bytes()
     }
 
-    fn serve_static_file(path: &str) -> Option<(String, Vec<u8>)> {
-        let decoded_path = match decode(path) {
-            Ok(data) => data,
-            Err(_) => return None,
-        };
-
-        let file_path = format!("public{}", decoded_path);
+    // The path was resolved under the document root
+    fn serve_static_file(file_path: &Path) -> Option<(String, ResponseBody)> {
+        let path = file_path.to_string_lossy();
 
-        if Path::new(&file_path).exists() {
-            let content = fs::read(&file_path).ok()?;
+        if file_path.is_file() {
+            let file = fs::File::open(file_path).ok()?;
+            let body = ResponseBody::from_file(file).ok()?;
             let mime_type = if path.ends_with(".css") {
                 "text/css"
             } else if path.ends_with(".js") {
@@ -422,9 +572,53 @@ impl HttpResponse {
             } else {
                 "application/octet-stream" // Default type for unknown files
             };
-            Some((mime_type.to_string(), content))
+            Some((mime_type.to_string(), body))
         } else {
             None
         }
     }
 }
+
+// Directory the templates and custom pages are read from
+fn pages_root(error_page: &Option<ErrorPages>) -> &Path {
+    error_page
+        .as_ref()
+        .map_or(Path::new(DEFAULT_ROOT), |error_page| error_page.root.as_path())
+}
+
+// A template of the server's root, or the built-in one when it has none
+fn template(root: &Path, name: &str, default: &str) -> String {
+    fs::read_to_string(root.join(name)).unwrap_or_else(|_| default.to_string())
+}
+
+// Message corresponding to each response's status
+fn reason_phrase(status_code: u16) -> &'static str {
+    match status_code {
+        200 => "OK",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
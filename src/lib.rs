@@ -7,4 +7,13 @@ pub mod cgi;
 pub mod cgi_handler;
 pub mod session;
 pub mod delete_file;
-pub mod request_queue;
\ No newline at end of file
+pub mod request_queue;
+pub mod connection;
+pub mod response_body;
+pub mod chunked;
+pub mod headers;
+pub mod signals;
+pub mod timer;
+pub mod path_resolver;
+pub mod router;
+pub mod rewrite;
\ No newline at end of file
@@ -1,8 +1,14 @@
 use localhost::config::load_config;
 
 fn main() {
-    let config = load_config("config.json").expect("Failed to load configuration");
+    let config = match load_config("config.json") {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("ERROR: loading configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
     if let Err(e) = config.start() {
         eprintln!("Error: {}", e);  
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+// Directory served by a server without a `root` option
+pub const DEFAULT_ROOT: &str = "public";
+
+// Files tried for a directory when its route has no `index` option
+pub const DEFAULT_INDEX: &str = "index.html";
+
+// Why a client-supplied path was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    Invalid,   // Bad percent-encoding or a NUL byte (400)
+    Forbidden, // The path leads out of the root (403)
+    NotFound,  // The root itself does not exist (404)
+}
+
+// Percent-decode the path of a request target. This must happen exactly once:
+// resolve() takes decoded paths and never decodes again, so "%252e%252e" stays
+// the literal name "%2e%2e" instead of becoming "..".
+pub fn percent_decode(path: &str) -> Result<String, PathError> {
+    urlencoding::decode(path)
+        .map(|path| path.into_owned())
+        .map_err(|_| PathError::Invalid)
+}
+
+// Encode a decoded path, or a part of one, for a URL: the characters with a
+// meaning in paths are kept, the others (controls, spaces, '%', '?', '#',
+// non-ASCII...) are percent-encoded
+pub fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// Remove the dot segments of a decoded path (RFC 3986, section 5.2.4). Backslashes
+// count as separators too. A ".." that would climb above the root is an error
+// rather than being clamped to it.
+pub fn normalize(path: &str) -> Result<Vec<&str>, PathError> {
+    if path.contains('\0') {
+        return Err(PathError::Invalid);
+    }
+    let mut segments = Vec::new();
+    for segment in path.split(['/', '\\']) {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    return Err(PathError::Forbidden);
+                }
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Ok(segments)
+}
+
+// A decoded request path without its dot segments, as it is routed: "/x/../cgi-bin/"
+// becomes "/cgi-bin/". A trailing slash, which names a directory, is kept.
+pub fn normalize_path(path: &str) -> Result<String, PathError> {
+    let segments = normalize(path)?;
+    let mut normalized = format!("/{}", segments.join("/"));
+    let last = path.rsplit(['/', '\\']).next().unwrap_or_default();
+    let directory = path.ends_with(['/', '\\']) || last == "." || last == "..";
+    if directory && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+// Map a decoded path, absolute or not, to a file under `root`. The part of the
+// path that exists is canonicalized, so a symbolic link pointing out of the root
+// is refused like a "..". The rest is appended as is, to name a file to create.
+pub fn resolve(root: &Path, path: &str) -> Result<PathBuf, PathError> {
+    let segments = normalize(path)?;
+    let root = root.canonicalize().map_err(|_| PathError::NotFound)?;
+
+    let mut resolved = root.clone();
+    for (index, segment) in segments.iter().enumerate() {
+        let next = resolved.join(segment);
+        if next.symlink_metadata().is_err() {
+            resolved.extend(&segments[index..]);
+            break;
+        }
+        // A dangling link can't be canonicalized, and writing through it could land anywhere
+        resolved = next.canonicalize().map_err(|_| PathError::Forbidden)?;
+        if !resolved.starts_with(&root) {
+            return Err(PathError::Forbidden);
+        }
+    }
+    Ok(resolved)
+}
+
+// Directory the files of a request are looked up in. With `root`, the whole request
+// path is appended to it; with `alias`, the path of the route is replaced by it, so a
+// route "/assets" aliased to "/srv/assets" maps "/assets/app.css" to "/srv/assets/app.css".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentRoot {
+    dir: PathBuf,
+    prefix: String, // Part of the request path replaced by `dir`, empty for a root
+}
+
+impl DocumentRoot {
+    pub fn root(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            prefix: String::new(),
+        }
+    }
+
+    pub fn alias(dir: impl Into<PathBuf>, prefix: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+        }
+    }
+
+    // File a decoded request path maps to
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, PathError> {
+        let path = match path.strip_prefix(self.prefix.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+            _ => path,
+        };
+        resolve(&self.dir, path)
+    }
+
+    // File named by the configuration (default_file, upload directory), relative to the directory
+    pub fn file(&self, name: &str) -> Result<PathBuf, PathError> {
+        resolve(&self.dir, name)
+    }
+}
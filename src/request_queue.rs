@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 
-use crate::{http_request::HttpRequest, http_response::HttpResponse};
+use crate::http_request::HttpRequest;
 
 #[derive(Debug)]
 pub struct RequestQueue {
@@ -16,15 +16,21 @@ impl RequestQueue {
         }
     }
 
-    pub fn push(&mut self, request: HttpRequest) -> Result<(), HttpResponse> {
-        if self.requests.len() >= self.max_queued {
-            return Err(HttpResponse::service_unavailable(None));
-        }
+    // Callers stop parsing once is_full, leaving the rest of the requests unread
+    pub fn push(&mut self, request: HttpRequest) {
+        debug_assert!(!self.is_full());
         self.requests.push_back(request);
-        Ok(())
     }
 
     pub fn pop(&mut self) -> Option<HttpRequest> {
         self.requests.pop_front()
     }
+
+    pub fn is_full(&self) -> bool {
+        self.requests.len() >= self.max_queued
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,123 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    os::fd::{AsRawFd, RawFd},
+};
+
+// Size of the slices pulled from files and streams before they are written out
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+// Non-blocking source of a body produced while it is sent, such as the output of a
+// CGI script. Reading it fails with WouldBlock when no data is ready yet.
+pub trait StreamSource: Read + AsRawFd + Send {}
+
+impl<T: Read + AsRawFd + Send> StreamSource for T {}
+
+// Body of an HTTP response, written to the client piece by piece by the event loop
+pub enum ResponseBody {
+    Bytes(Vec<u8>),
+    File { file: File, offset: u64, len: u64 },
+    Stream(Box<dyn StreamSource>), // Length unknown in advance; the event loop polls its fd
+}
+
+impl ResponseBody {
+    pub fn empty() -> Self {
+        ResponseBody::Bytes(Vec::new())
+    }
+
+    // Serve a whole file from disk without loading it in memory
+    pub fn from_file(file: File) -> std::io::Result<Self> {
+        let len = file.metadata()?.len();
+        Ok(ResponseBody::File {
+            file,
+            offset: 0,
+            len,
+        })
+    }
+
+    // Number of bytes of the body, when it is known before sending it
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            ResponseBody::Bytes(bytes) => Some(bytes.len() as u64),
+            ResponseBody::File { len, .. } => Some(*len),
+            ResponseBody::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    // Descriptor to wait on when a stream has no data ready
+    pub fn stream_fd(&self) -> Option<RawFd> {
+        match self {
+            ResponseBody::Stream(source) => Some(source.as_raw_fd()),
+            _ => None,
+        }
+    }
+
+    // Read the next slice of the body; an empty vector means the body is exhausted.
+    // A stream with nothing to read yet fails with WouldBlock.
+    pub fn next_chunk(&mut self) -> std::io::Result<Vec<u8>> {
+        match self {
+            ResponseBody::Bytes(bytes) => Ok(std::mem::take(bytes)),
+            ResponseBody::File { file, offset, len } => {
+                if *len == 0 {
+                    return Ok(Vec::new());
+                }
+                let size = (*len).min(CHUNK_SIZE as u64) as usize;
+                let mut chunk = vec![0; size];
+                file.seek(SeekFrom::Start(*offset))?;
+                file.read_exact(&mut chunk)?;
+                *offset += size as u64;
+                *len -= size as u64;
+                Ok(chunk)
+            }
+            ResponseBody::Stream(source) => {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let n = loop {
+                    match source.read(&mut chunk) {
+                        Ok(n) => break n,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    }
+                };
+                chunk.truncate(n);
+                Ok(chunk)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseBody::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            ResponseBody::File { offset, len, .. } => {
+                write!(f, "File {{ offset: {}, len: {} }}", offset, len)
+            }
+            ResponseBody::Stream(_) => write!(f, "Stream"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for ResponseBody {
+    fn from(bytes: Vec<u8>) -> Self {
+        ResponseBody::Bytes(bytes)
+    }
+}
+
+impl From<String> for ResponseBody {
+    fn from(text: String) -> Self {
+        ResponseBody::Bytes(text.into_bytes())
+    }
+}
+
+// How the end of a body is signalled to the client
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Framing {
+    ContentLength, // Size announced up front
+    Chunked,       // Size unknown, HTTP/1.1 chunked transfer coding
+    Close,         // Size unknown and the client cannot decode chunks: the connection end marks it
+}
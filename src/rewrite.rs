@@ -0,0 +1,89 @@
+use regex::Regex;
+
+use crate::config::RewriteRule;
+
+// What to do once a rule has rewritten the path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RewriteFlag {
+    Continue, // No flag: try the next rules of the route, then route again
+    Last,     // Route the new path again at once
+    Break,    // Serve the new path from the current route
+}
+
+// Result of the rewrite rules of a route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rewritten {
+    Unchanged,
+    Reroute,
+    Break,
+}
+
+// A rewrite rule with its pattern compiled
+#[derive(Debug)]
+pub struct Rewrite {
+    regex: Regex,
+    replacement: String,
+    flag: RewriteFlag,
+}
+
+impl Rewrite {
+    pub fn compile(rule: &RewriteRule) -> Result<Self, String> {
+        let regex = Regex::new(&rule.pattern)
+            .map_err(|e| format!("invalid rewrite pattern '{}': {}", rule.pattern, e))?;
+        let flag = match rule.flag.as_deref() {
+            None => RewriteFlag::Continue,
+            Some("last") => RewriteFlag::Last,
+            Some("break") => RewriteFlag::Break,
+            Some(flag) => return Err(format!("unknown rewrite flag '{}'", flag)),
+        };
+        if !rule.replacement.starts_with('/') {
+            return Err(format!(
+                "rewrite replacement '{}' must start with '/'",
+                rule.replacement
+            ));
+        }
+        Ok(Self {
+            regex,
+            replacement: rule.replacement.clone(),
+            flag,
+        })
+    }
+}
+
+// Apply the rules of a route, in order, to a decoded path. "$1" or "${name}" in a
+// replacement stand for the groups of the pattern. A replacement with a query sets
+// new arguments, followed by the ones of the request; ending it with '?' drops them.
+pub fn apply(rules: &[Rewrite], path: &mut String, query: &mut Option<String>) -> Rewritten {
+    let mut changed = false;
+    for rule in rules {
+        let Some(captures) = rule.regex.captures(path) else {
+            continue;
+        };
+        let mut target = String::new();
+        captures.expand(&rule.replacement, &mut target);
+
+        match target.split_once('?') {
+            Some((new_path, new_query)) => {
+                *query = match (new_query, query.take()) {
+                    ("", _) => None,
+                    (new_query, Some(args)) => Some(format!("{}&{}", new_query, args)),
+                    (new_query, None) => Some(new_query.to_string()),
+                };
+                *path = new_path.to_string();
+            }
+            None => *path = target,
+        }
+        changed = true;
+
+        match rule.flag {
+            RewriteFlag::Continue => {}
+            RewriteFlag::Last => return Rewritten::Reroute,
+            RewriteFlag::Break => return Rewritten::Break,
+        }
+    }
+    if changed {
+        Rewritten::Reroute
+    } else {
+        Rewritten::Unchanged
+    }
+}
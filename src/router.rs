@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::config::RouteConfig;
+use crate::path_resolver::normalize_path;
+use crate::rewrite::{self, Rewrite, Rewritten};
+
+// Routing passes a request may go through before its rewrites are taken for a loop
+const MAX_REROUTES: usize = 10;
+// Statuses a route may redirect with
+const REDIRECT_STATUSES: [u16; 5] = [301, 302, 303, 307, 308];
+
+// How the key of a route in the configuration is matched against a request path:
+//   "= /path"   the path and nothing else
+//   "~ regex"   a regular expression, "~* regex" ignoring case
+//   "/users/:id/avatar", "/files/*rest"
+//               a pattern: ":name" stands for one segment, "*name" for all the
+//               segments left, if any (only as the last segment)
+//   "/path"     the path and everything below it ("/path/...", but not "/pathname")
+#[derive(Debug)]
+enum Matcher {
+    Exact(String),
+    Regex(Regex),
+    Pattern(Vec<Segment>),
+    Prefix(String),
+}
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+impl Matcher {
+    // Names of the parameters a request matched by the route may have
+    fn param_names(&self) -> Vec<String> {
+        match self {
+            Matcher::Pattern(segments) => segments
+                .iter()
+                .filter_map(|segment| match segment {
+                    Segment::Param(name) | Segment::Rest(name) => Some(name.clone()),
+                    Segment::Literal(_) => None,
+                })
+                .collect(),
+            Matcher::Regex(regex) => regex
+                .capture_names()
+                .enumerate()
+                .skip(1)
+                .map(|(number, name)| name.map_or(number.to_string(), str::to_string))
+                .collect(),
+            Matcher::Exact(_) | Matcher::Prefix(_) => Vec::new(),
+        }
+    }
+}
+
+// The route chosen for a request
+#[derive(Debug)]
+pub struct RouteMatch<'a> {
+    pub key: &'a str, // Key of the route in the configuration
+    pub config: &'a RouteConfig,
+    pub prefix: &'a str, // Request path of the route itself, replaced by an alias
+    pub own_path: bool,  // The request names the route, not a file below it
+    pub params: Vec<(String, String)>, // Pattern parameters, or regex groups by name or number
+    index: usize,
+}
+
+#[derive(Debug)]
+struct Route {
+    key: String,
+    config: RouteConfig,
+    matcher: Matcher,
+    rewrites: Vec<Rewrite>,
+}
+
+// Routing table of a server, compiled once when the configuration is loaded.
+// The first of these wins:
+//   1. an exact route ("= /path")
+//   2. a prefix route whose path is the request path itself
+//   3. a pattern route, the one with the most literal segments first
+//   4. a regex route, the longest pattern first
+//   5. the prefix route with the longest path
+// JSON objects have no defined order, so no rule depends on the order of the routes.
+#[derive(Debug)]
+pub struct Router {
+    routes: Vec<Route>,
+    exact: HashMap<String, usize>,
+    patterns: Vec<usize>, // Most literal segments first
+    regexes: Vec<usize>,  // Longest pattern first
+    prefixes: Vec<usize>, // Longest path first
+}
+
+impl Router {
+    pub fn new(routes: &HashMap<String, RouteConfig>) -> Result<Self, String> {
+        let mut router = Router {
+            routes: Vec::new(),
+            exact: HashMap::new(),
+            patterns: Vec::new(),
+            regexes: Vec::new(),
+            prefixes: Vec::new(),
+        };
+
+        // Sorted so that equal-length patterns are always tried in the same order
+        let mut keys: Vec<&String> = routes.keys().collect();
+        keys.sort();
+
+        for key in keys {
+            let index = router.routes.len();
+            let matcher = if let Some(path) = key.strip_prefix('=') {
+                let path = path.trim_start().to_string();
+                router.exact.insert(path.clone(), index);
+                Matcher::Exact(path)
+            } else if let Some(pattern) = key.strip_prefix('~') {
+                let (pattern, ignore_case) = match pattern.strip_prefix('*') {
+                    Some(pattern) => (pattern, true),
+                    None => (pattern, false),
+                };
+                let regex = RegexBuilder::new(pattern.trim_start())
+                    .case_insensitive(ignore_case)
+                    .build()
+                    .map_err(|e| format!("route '{}': invalid regex: {}", key, e))?;
+                router.regexes.push(index);
+                Matcher::Regex(regex)
+            } else if let Some(segments) = parse_pattern(key)? {
+                router.patterns.push(index);
+                Matcher::Pattern(segments)
+            } else {
+                router.prefixes.push(index);
+                Matcher::Prefix(key.clone())
+            };
+            let rewrites = routes[key]
+                .rewrites
+                .iter()
+                .flatten()
+                .map(Rewrite::compile)
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("route '{}': {}", key, e))?;
+            if let Some(status) = routes[key].redirect_status {
+                if !REDIRECT_STATUSES.contains(&status) {
+                    return Err(format!(
+                        "route '{}': invalid 'redirect_status' {}",
+                        key, status
+                    ));
+                }
+            }
+            if let Some(target) = &routes[key].redirection {
+                // A variable the route cannot set would silently vanish from the target
+                let names = matcher.param_names();
+                let mut unknown = None;
+                expand_variables(target, |name| {
+                    if name != "rest" && !names.iter().any(|known| known == name) {
+                        unknown.get_or_insert_with(|| name.to_string());
+                    }
+                    String::new()
+                });
+                if let Some(name) = unknown {
+                    return Err(format!(
+                        "route '{}': 'redirection' uses '${}', which the route does not set",
+                        key, name
+                    ));
+                }
+            }
+            router.routes.push(Route {
+                key: key.clone(),
+                config: routes[key].clone(),
+                matcher,
+                rewrites,
+            });
+        }
+
+        let len = |index: &usize| match &router.routes[*index].matcher {
+            Matcher::Regex(regex) => regex.as_str().len(),
+            Matcher::Pattern(segments) => segments
+                .iter()
+                .filter(|segment| matches!(segment, Segment::Literal(_)))
+                .count(),
+            Matcher::Exact(path) | Matcher::Prefix(path) => path.len(),
+        };
+        router
+            .patterns
+            .sort_by_key(|index| std::cmp::Reverse(len(index)));
+        router
+            .regexes
+            .sort_by_key(|index| std::cmp::Reverse(len(index)));
+        router
+            .prefixes
+            .sort_by_key(|index| std::cmp::Reverse(len(index)));
+        Ok(router)
+    }
+
+    // Route a request, following the rewrite rules of the routes it meets on the way.
+    // The path, without dot segments, and the query are replaced by the rewritten ones.
+    // Routing again more than MAX_REROUTES times is taken for a rewrite loop.
+    pub fn route(
+        &self,
+        path: &mut String,
+        query: &mut Option<String>,
+    ) -> Result<Option<RouteMatch<'_>>, String> {
+        for _ in 0..MAX_REROUTES {
+            let Some(found) = self.find(path) else {
+                return Ok(None);
+            };
+            let rewritten = rewrite::apply(&self.routes[found.index].rewrites, path, query);
+            if rewritten != Rewritten::Unchanged {
+                // A group of the pattern may bring dot segments back in
+                *path = normalize_path(path)
+                    .map_err(|_| format!("rewrite leads out of the root: {}", path))?;
+            }
+            match rewritten {
+                Rewritten::Unchanged => return Ok(Some(found)),
+                Rewritten::Reroute => continue,
+                Rewritten::Break => {
+                    let own_path = self.is_own_path(found.index, path);
+                    return Ok(Some(RouteMatch { own_path, ..found }));
+                }
+            }
+        }
+        Err(format!("rewrite loop, routed {} times: {}", MAX_REROUTES, path))
+    }
+
+    // Route of a decoded request path, if any, without rewriting it
+    pub fn find(&self, path: &str) -> Option<RouteMatch<'_>> {
+        if let Some(&index) = self.exact.get(path) {
+            return Some(self.route_match(index, true, Vec::new()));
+        }
+
+        if let Some(&index) = self
+            .prefixes
+            .iter()
+            .find(|&&index| self.routes[index].key == path)
+        {
+            return Some(self.route_match(index, true, Vec::new()));
+        }
+
+        for &index in &self.patterns {
+            let Matcher::Pattern(segments) = &self.routes[index].matcher else {
+                continue;
+            };
+            if let Some(params) = match_pattern(segments, path) {
+                return Some(self.route_match(index, true, params));
+            }
+        }
+
+        for &index in &self.regexes {
+            let Matcher::Regex(regex) = &self.routes[index].matcher else {
+                continue;
+            };
+            if let Some(found) = regex.captures(path) {
+                let params = regex
+                    .capture_names()
+                    .enumerate()
+                    .skip(1)
+                    .filter_map(|(number, name)| {
+                        let value = found.get(number)?.as_str().to_string();
+                        Some((name.map_or(number.to_string(), str::to_string), value))
+                    })
+                    .collect();
+                return Some(self.route_match(index, false, params));
+            }
+        }
+
+        let index = *self
+            .prefixes
+            .iter()
+            .find(|&&index| is_below(path, &self.routes[index].key))?;
+        Some(self.route_match(index, false, Vec::new()))
+    }
+
+    fn route_match(
+        &self,
+        index: usize,
+        own_path: bool,
+        params: Vec<(String, String)>,
+    ) -> RouteMatch<'_> {
+        let route = &self.routes[index];
+        // A regex or a pattern stands for no particular path: an alias replaces none of it
+        let prefix = match &route.matcher {
+            Matcher::Exact(path) => path.as_str(),
+            Matcher::Prefix(path) => path.trim_end_matches('/'),
+            Matcher::Regex(_) | Matcher::Pattern(_) => "",
+        };
+        RouteMatch {
+            key: &route.key,
+            config: &route.config,
+            prefix,
+            own_path,
+            params,
+            index,
+        }
+    }
+
+    // Whether a path rewritten inside a route names the route itself
+    fn is_own_path(&self, index: usize, path: &str) -> bool {
+        match &self.routes[index].matcher {
+            Matcher::Exact(exact) => exact == path,
+            Matcher::Prefix(prefix) => prefix == path,
+            Matcher::Pattern(segments) => match_pattern(segments, path).is_some(),
+            Matcher::Regex(_) => false,
+        }
+    }
+}
+
+// Whether `path` is `prefix` or a path below it, on a segment boundary
+fn is_below(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+// Segments of a pattern route, or None for a key without ":name" or "*name"
+fn parse_pattern(key: &str) -> Result<Option<Vec<Segment>>, String> {
+    let Some(path) = key.strip_prefix('/') else {
+        return Ok(None);
+    };
+    let segments: Vec<Segment> = path
+        .split('/')
+        .map(|part| match part.chars().next() {
+            Some(':') => Segment::Param(part[1..].to_string()),
+            Some('*') => Segment::Rest(part[1..].to_string()),
+            _ => Segment::Literal(part.to_string()),
+        })
+        .collect();
+    if segments
+        .iter()
+        .all(|segment| matches!(segment, Segment::Literal(_)))
+    {
+        return Ok(None);
+    }
+
+    let mut names = Vec::new();
+    for (position, segment) in segments.iter().enumerate() {
+        let name = match segment {
+            Segment::Literal(_) => continue,
+            Segment::Param(name) => name,
+            Segment::Rest(name) => {
+                if position + 1 != segments.len() {
+                    return Err(format!(
+                        "route '{}': '*{}' must be the last segment",
+                        key, name
+                    ));
+                }
+                name
+            }
+        };
+        let valid = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!(
+                "route '{}': invalid parameter name '{}'",
+                key, name
+            ));
+        }
+        if names.contains(&name) {
+            return Err(format!(
+                "route '{}': parameter '{}' is used twice",
+                key, name
+            ));
+        }
+        names.push(name);
+    }
+    Ok(Some(segments))
+}
+
+// Values of the parameters of a pattern, if `path` matches it
+fn match_pattern(segments: &[Segment], path: &str) -> Option<Vec<(String, String)>> {
+    let parts: Vec<&str> = path.strip_prefix('/')?.split('/').collect();
+    let mut params = Vec::new();
+    for (position, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Rest(name) => {
+                let rest = parts.get(position..).unwrap_or_default().join("/");
+                params.push((name.clone(), rest));
+                return Some(params);
+            }
+            Segment::Literal(literal) => {
+                if parts.get(position) != Some(&literal.as_str()) {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                let value = parts.get(position).filter(|value| !value.is_empty())?;
+                params.push((name.clone(), value.to_string()));
+            }
+        }
+    }
+    (parts.len() == segments.len()).then_some(params)
+}
+
+// Replace the variables of a redirection target, $name or ${name}, by what `value`
+// returns for their name. A '$' that starts no variable is kept as written.
+pub fn expand_variables(template: &str, mut value: impl FnMut(&str) -> String) -> String {
+    let mut expanded = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+        let braced = chars.next_if_eq(&'{').is_some();
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            name.push(c);
+        }
+        if name.is_empty() || (braced && chars.next_if_eq(&'}').is_none()) {
+            // Not a variable: keep it as written
+            expanded.push('$');
+            expanded.push_str(if braced { "{" } else { "" });
+            expanded.push_str(&name);
+            continue;
+        }
+        expanded.push_str(&value(&name));
+    }
+    expanded
+}
@@ -82,9 +82,7 @@ impl SessionManager {
             RouteConfig {
                 accepted_methods: Some(vec!["GET".to_string()]),
                 default_file: Some("session.html".to_string()),
-                redirection: None,
-                cgi: None,
-                directory_listing: None,
+                ..RouteConfig::default()
             },
         );
 
@@ -93,10 +91,7 @@ impl SessionManager {
             "/create-session".to_string(),
             RouteConfig {
                 accepted_methods: Some(vec!["POST".to_string()]),
-                default_file: None,
-                redirection: None,
-                cgi: None,
-                directory_listing: None,
+                ..RouteConfig::default()
             },
         );
 
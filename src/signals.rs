@@ -0,0 +1,75 @@
+use std::{io, mem, os::fd::RawFd};
+
+// Signals handled by the server instead of killing it
+pub const HANDLED_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+// A signalfd that turns the handled signals into readable events.
+// The signals are blocked in the calling thread, and in every thread it spawns
+// afterwards, so it must be created before the workers are started.
+#[derive(Debug)]
+pub struct SignalListener {
+    pub fd: RawFd,
+}
+
+impl SignalListener {
+    pub fn install() -> io::Result<Self> {
+        let mut mask: libc::sigset_t = unsafe { mem::zeroed() };
+        unsafe {
+            libc::sigemptyset(&mut mask);
+            for signal in HANDLED_SIGNALS {
+                libc::sigaddset(&mut mask, signal);
+            }
+        }
+
+        let res = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut()) };
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res));
+        }
+
+        let fd = unsafe { libc::signalfd(-1, &mask, libc::SFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd })
+    }
+
+    // Wait up to `timeout_ms` milliseconds for a signal and return its number
+    pub fn wait(&self, timeout_ms: i32) -> io::Result<Option<libc::c_int>> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(err);
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+
+        let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+        let size = mem::size_of::<libc::signalfd_siginfo>();
+        let read = unsafe {
+            libc::read(
+                self.fd,
+                &mut info as *mut libc::signalfd_siginfo as *mut libc::c_void,
+                size,
+            )
+        };
+        if read != size as isize {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(info.ssi_signo as libc::c_int))
+    }
+}
+
+impl Drop for SignalListener {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    os::fd::RawFd,
+    time::{Duration, Instant},
+};
+
+// Resolution of the wheel: deadlines are rounded up to the next tick
+pub const TICK: Duration = Duration::from_millis(100);
+// Number of slots, i.e. one revolution covers SLOTS * TICK
+const SLOTS: usize = 512;
+
+#[derive(Debug)]
+struct Entry {
+    fd: RawFd,
+    token: u64,
+    tick: u64, // Absolute tick at which the entry expires
+}
+
+// Hashed timer wheel for connection deadlines. Timers are never removed:
+// each one carries the token the connection had when it was armed, and an
+// expired timer whose token is no longer the connection's current one is stale.
+#[derive(Debug)]
+pub struct TimerWheel {
+    slots: Vec<Vec<Entry>>,
+    start: Instant,
+    current_tick: u64, // Next tick to be processed
+    len: usize,
+}
+
+impl TimerWheel {
+    pub fn new() -> Self {
+        Self {
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            start: Instant::now(),
+            current_tick: 0,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn elapsed_ticks(&self, instant: Instant) -> (u64, bool) {
+        let elapsed = instant.saturating_duration_since(self.start).as_nanos();
+        let tick = TICK.as_nanos();
+        ((elapsed / tick) as u64, !elapsed.is_multiple_of(tick))
+    }
+
+    pub fn schedule(&mut self, fd: RawFd, token: u64, deadline: Instant) {
+        // Round up so that a timer never fires before its deadline; one whose
+        // tick was already processed fires on the next expire call
+        let (ticks, partial) = self.elapsed_ticks(deadline);
+        let tick = (ticks + partial as u64).max(self.current_tick);
+        self.slots[tick as usize % SLOTS].push(Entry { fd, token, tick });
+        self.len += 1;
+    }
+
+    // Remove and return the (fd, token) of every timer due at `now`
+    pub fn expire(&mut self, now: Instant) -> Vec<(RawFd, u64)> {
+        let (now_tick, _) = self.elapsed_ticks(now);
+        let mut expired = Vec::new();
+
+        if self.len > 0 {
+            // After a long sleep, every slot is visited once
+            let first = self
+                .current_tick
+                .max((now_tick + 1).saturating_sub(SLOTS as u64));
+            for tick in first..=now_tick {
+                let slot = &mut self.slots[tick as usize % SLOTS];
+                let mut i = 0;
+                while i < slot.len() {
+                    if slot[i].tick <= now_tick {
+                        let entry = slot.swap_remove(i);
+                        expired.push((entry.fd, entry.token));
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            self.len -= expired.len();
+        }
+
+        self.current_tick = now_tick;
+        expired
+    }
+
+    // How long epoll_wait may sleep before the earliest timer is due
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        let tick = self.next_tick()?;
+        let due = self.start + Duration::from_nanos(TICK.as_nanos() as u64 * tick);
+        Some(due.saturating_duration_since(now))
+    }
+
+    // Earliest tick holding a timer. Empty slots are skipped, and an entry of a slot
+    // may belong to a later revolution of the wheel.
+    fn next_tick(&self) -> Option<u64> {
+        if self.is_empty() {
+            return None;
+        }
+        for tick in self.current_tick..self.current_tick + SLOTS as u64 {
+            let slot = &self.slots[tick as usize % SLOTS];
+            if slot.iter().any(|entry| entry.tick <= tick) {
+                return Some(tick);
+            }
+        }
+        // Every timer is more than a revolution away
+        self.slots.iter().flatten().map(|entry| entry.tick).min()
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,110 @@
+// A chunked request body is decoded however it is split across reads: handlers get
+// the plain body with its Content-Length, and the trailers as extra headers.
+
+use std::net::{TcpListener, TcpStream};
+
+use localhost::{
+    chunked::{ChunkedDecoder, DecodeStatus},
+    connection::{Connection, ParseOutcome, Timeouts, DEFAULT_MAX_REQUESTS},
+    http_request::HttpRequest,
+};
+
+const REQUEST: &[u8] = b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+    5;name=first\r\nhello\r\n6\r\n world\r\n0\r\nX-Checksum: 42\r\nHost: b\r\n\r\n";
+
+fn connection() -> Connection {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    Connection::new(stream, -1, Timeouts::default(), DEFAULT_MAX_REQUESTS)
+}
+
+// Feed `payload` to the connection `piece` bytes at a time, parsing after each piece
+fn parse_in_pieces(payload: &[u8], piece: usize) -> Vec<String> {
+    let mut connection = connection();
+    let mut outcomes = Vec::new();
+    for bytes in payload.chunks(piece) {
+        connection.read_buf.extend_from_slice(bytes);
+        loop {
+            match connection.next_request() {
+                ParseOutcome::Incomplete => break,
+                ParseOutcome::Headers => outcomes.push("Headers".to_string()),
+                ParseOutcome::Complete(request) => outcomes.push(describe(&request)),
+                error => {
+                    outcomes.push(format!("{:?}", error));
+                    return outcomes;
+                }
+            }
+        }
+    }
+    outcomes
+}
+
+fn describe(request: &HttpRequest) -> String {
+    format!(
+        "{:?} length={:?} te={:?} checksum={:?} host={:?}",
+        String::from_utf8_lossy(&request.body),
+        request.headers.get("Content-Length"),
+        request.headers.get("Transfer-Encoding"),
+        request.headers.get("X-Checksum"),
+        request.headers.get("Host"),
+    )
+}
+
+#[test]
+fn body_and_trailers_survive_any_split() {
+    let expected = [
+        "Headers".to_string(),
+        r#""hello world" length=Some("11") te=None checksum=Some("42") host=Some("a")"#.to_string(),
+    ];
+    for piece in [1, 2, 3, 7, 64, REQUEST.len()] {
+        assert_eq!(
+            parse_in_pieces(REQUEST, piece),
+            expected,
+            "{piece} bytes at a time"
+        );
+    }
+}
+
+#[test]
+fn body_over_the_limit_is_refused_while_decoding() {
+    let mut connection = connection();
+    connection.read_buf.extend_from_slice(REQUEST);
+    assert!(matches!(connection.next_request(), ParseOutcome::Headers));
+    connection.body_limit = Some(8);
+    assert!(matches!(connection.next_request(), ParseOutcome::TooLarge));
+}
+
+#[test]
+fn decoder_stops_at_the_end_of_the_body() {
+    let mut decoder = ChunkedDecoder::new();
+    let mut body = Vec::new();
+
+    let mut input = b"3\r\nab".to_vec();
+    assert_eq!(
+        decoder.decode(&mut input, &mut body, None),
+        DecodeStatus::NeedMore
+    );
+    assert!(input.is_empty());
+
+    input.extend_from_slice(b"c\r\n0\r\nExpires: never\r\n\r\nGET / HTTP/1.1\r\n");
+    assert_eq!(
+        decoder.decode(&mut input, &mut body, None),
+        DecodeStatus::Done
+    );
+    assert_eq!(body, b"abc");
+    assert_eq!(
+        decoder.trailers,
+        [("Expires".to_string(), "never".to_string())]
+    );
+    assert_eq!(input, b"GET / HTTP/1.1\r\n");
+}
+
+#[test]
+fn malformed_trailers_are_refused() {
+    for trailer in ["no colon", ": empty name", "Bad Name: 1"] {
+        let mut input = format!("0\r\n{trailer}\r\n\r\n").into_bytes();
+        let status = ChunkedDecoder::new().decode(&mut input, &mut Vec::new(), None);
+        assert_eq!(status, DecodeStatus::Invalid, "{trailer:?}");
+    }
+}
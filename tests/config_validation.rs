@@ -0,0 +1,115 @@
+// A configuration is checked as a whole when it is loaded, at startup as on a
+// reload: any invalid value rejects the file.
+
+use localhost::config::{load_config, Config};
+use serde_json::{json, Value};
+
+// A valid configuration with one server, after `change` edited it
+fn config(change: impl FnOnce(&mut Value)) -> Config {
+    let mut config = json!({
+        "servers": [{
+            "name": "main",
+            "addr": "127.0.0.1",
+            "ports": ["8080"],
+            "root": "public",
+            "error_pages": { "404": "errors/404.html" },
+            "routes": {
+                "/": { "accepted_methods": ["GET"] },
+                "/old": { "redirection": "/new$rest", "redirect_status": 308 },
+                "/users/:id": { "redirection": "/profiles/$id" },
+                "/app": { "try_files": ["$uri", "/index.html"], "index": ["index.htm"] }
+            }
+        }]
+    });
+    change(&mut config);
+    serde_json::from_value(config).unwrap()
+}
+
+fn server(config: &mut Value) -> &mut Value {
+    &mut config["servers"][0]
+}
+
+fn route<'a>(config: &'a mut Value, path: &str) -> &'a mut Value {
+    &mut server(config)["routes"][path]
+}
+
+#[test]
+fn valid_configuration_is_accepted() {
+    assert_eq!(config(|_| {}).validate(), Ok(()));
+}
+
+#[test]
+fn invalid_values_are_refused() {
+    type Change = fn(&mut Value);
+    let cases: [(&str, Change); 22] = [
+        ("no server", |c| c["servers"] = json!([])),
+        ("workers", |c| c["workers"] = json!(0)),
+        ("empty name", |c| server(c)["name"] = json!(" ")),
+        ("invalid address", |c| {
+            server(c)["addr"] = json!("localhost")
+        }),
+        ("invalid port", |c| server(c)["ports"] = json!(["0"])),
+        ("invalid port", |c| server(c)["ports"] = json!(["80a"])),
+        ("non-error status", |c| {
+            server(c)["error_pages"] = json!({ "302": "x.html" })
+        }),
+        ("keepalive_timeout", |c| {
+            server(c)["keepalive_timeout"] = json!(0)
+        }),
+        ("request_line_limit", |c| {
+            server(c)["request_line_limit"] = json!(0)
+        }),
+        ("'root' is empty", |c| server(c)["root"] = json!("")),
+        ("both 'root' and 'alias'", |c| {
+            *route(c, "/") = json!({ "root": "a", "alias": "b" })
+        }),
+        ("empty 'root' or 'alias'", |c| {
+            route(c, "/")["alias"] = json!(" ")
+        }),
+        ("unknown method", |c| {
+            route(c, "/")["accepted_methods"] = json!(["GET", "FETCH"])
+        }),
+        ("unknown method", |c| {
+            route(c, "/")["accepted_methods"] = json!(["get"])
+        }),
+        ("invalid 'redirect_status'", |c| {
+            route(c, "/old")["redirect_status"] = json!(200)
+        }),
+        ("not a file name", |c| {
+            route(c, "/app")["index"] = json!(["a/b.html"])
+        }),
+        ("not a file name", |c| {
+            route(c, "/app")["index"] = json!([""])
+        }),
+        ("empty 'try_files'", |c| {
+            route(c, "/app")["try_files"] = json!([])
+        }),
+        ("invalid 'try_files' entry", |c| {
+            route(c, "/app")["try_files"] = json!(["$uri", "index.html"])
+        }),
+        ("invalid 'try_files' entry", |c| {
+            route(c, "/app")["try_files"] = json!(["$uri", "=200"])
+        }),
+        ("invalid regex", |c| {
+            server(c)["routes"]["~ ^/(a"] = json!({})
+        }),
+        ("does not set", |c| {
+            route(c, "/users/:id")["redirection"] = json!("/profiles/$name")
+        }),
+    ];
+    for (expected, change) in cases {
+        let err = config(change).validate().unwrap_err();
+        assert!(err.contains(expected), "expected {expected:?}, got {err:?}");
+    }
+}
+
+#[test]
+fn invalid_file_is_not_loaded() {
+    let path = std::env::temp_dir().join(format!("localhost-config-{}.json", std::process::id()));
+    std::fs::write(&path, json!({ "servers": [], "workers": 2 }).to_string()).unwrap();
+    let err = load_config(path.to_str().unwrap()).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("no server"), "{err}");
+}
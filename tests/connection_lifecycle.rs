@@ -0,0 +1,678 @@
+// What a client sees on the wire from a running worker: interim responses, timeouts,
+// pipelined responses and size limits.
+
+mod common;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::fd::AsRawFd,
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use localhost::{
+    config::{bind_reuseport, RouteConfig},
+    connection::{HeaderLimits, Timeouts, DEFAULT_MAX_REQUESTS},
+    event_loop::{Control, Controller, EventLoop, ServerSetup, WorkerSetup},
+    router::Router,
+    session::SessionManager,
+};
+
+use common::TempDir;
+
+// A worker serving public/ on a free port, shut down when dropped
+struct TestServer {
+    addr: SocketAddr,
+    controller: Controller,
+    thread: Option<JoinHandle<()>>,
+    // Directory made for this server alone, removed once it is shut down
+    _root: Option<TempDir>,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.controller.send(Control::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+// Start a worker whose only server has a "/" route accepting GET and POST,
+// after letting `configure` change its settings
+fn serve(configure: impl FnOnce(&mut ServerSetup)) -> TestServer {
+    serve_on(TcpListener::bind("127.0.0.1:0").unwrap(), configure)
+}
+
+// Same as serve, on a listener bound by the caller
+fn serve_on(listener: TcpListener, configure: impl FnOnce(&mut ServerSetup)) -> TestServer {
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let routes = HashMap::from([(
+        "/".to_string(),
+        RouteConfig {
+            accepted_methods: Some(vec!["GET".to_string(), "POST".to_string()]),
+            ..RouteConfig::default()
+        },
+    )]);
+    let mut server = ServerSetup {
+        name: "test".to_string(),
+        addresses: vec![addr.to_string()],
+        router: Arc::new(Router::new(&routes).unwrap()),
+        root: "public".to_string(),
+        error_pages: None,
+        size_limit: None,
+        timeouts: Timeouts::default(),
+        max_requests: DEFAULT_MAX_REQUESTS,
+        header_limits: HeaderLimits::default(),
+    };
+    configure(&mut server);
+
+    let mut event_loop = EventLoop::new().unwrap();
+    event_loop
+        .apply_setup(WorkerSetup {
+            servers: vec![server],
+            new_listeners: vec![(addr.to_string(), listener)],
+            drain_timeout: Duration::from_millis(100),
+        })
+        .unwrap();
+    let controller = event_loop.controller();
+    let thread = std::thread::spawn(move || event_loop.run().unwrap());
+    TestServer {
+        addr,
+        controller,
+        thread: Some(thread),
+        _root: None,
+    }
+}
+
+fn connect(server: &TestServer) -> TcpStream {
+    let stream = TcpStream::connect(server.addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    stream
+}
+
+// Everything received until the server closes the connection, or stops sending for
+// the read timeout
+fn read_all(stream: &mut TcpStream) -> String {
+    String::from_utf8_lossy(&read_bytes(stream)).into_owned()
+}
+
+// Same as read_all, for a binary body
+fn read_bytes(stream: &mut TcpStream) -> Vec<u8> {
+    let mut received = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => received.extend_from_slice(&buf[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => break,
+            Err(e) => panic!("{e}"),
+        }
+    }
+    received
+}
+
+// Bytes received up to `end`, included
+fn read_until(stream: &mut TcpStream, end: &str) -> String {
+    let mut received = Vec::new();
+    let mut byte = [0; 1];
+    while !received.ends_with(end.as_bytes()) && stream.read(&mut byte).unwrap() == 1 {
+        received.push(byte[0]);
+    }
+    String::from_utf8_lossy(&received).into_owned()
+}
+
+// Bytes received up to the end of the first head
+fn read_head(stream: &mut TcpStream) -> String {
+    read_until(stream, "\r\n\r\n")
+}
+
+// Status lines of the responses in `received`, in order
+fn statuses(received: &str) -> Vec<&str> {
+    received
+        .match_indices("HTTP/1.1 ")
+        .map(|(start, _)| {
+            let line = &received[start..];
+            &line[..line.find("\r\n").unwrap_or(line.len())]
+        })
+        .collect()
+}
+
+#[test]
+fn continue_is_sent_before_the_body_is() {
+    let server = serve(|_| {});
+    let mut client = connect(&server);
+    client
+        .write_all(b"POST /x HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 5\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_head(&mut client), "HTTP/1.1 100 Continue\r\n\r\n");
+
+    client.write_all(b"hello").unwrap();
+    assert_eq!(statuses(&read_all(&mut client)), ["HTTP/1.1 404 Not Found"]);
+}
+
+#[test]
+fn no_continue_once_the_body_has_arrived() {
+    let server = serve(|_| {});
+    let mut client = connect(&server);
+    client
+        .write_all(b"POST /x HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello")
+        .unwrap();
+    // Sent once the first request is answered and anything that follows it is queued
+    std::thread::sleep(Duration::from_millis(100));
+    client
+        .write_all(b"GET /y HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    assert_eq!(
+        statuses(&read_all(&mut client)),
+        ["HTTP/1.1 404 Not Found", "HTTP/1.1 404 Not Found"]
+    );
+}
+
+#[test]
+fn disallowed_method_is_refused_before_the_body() {
+    let server = serve(|_| {});
+    let mut client = connect(&server);
+    client
+        .write_all(
+            b"PUT /x HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n",
+        )
+        .unwrap();
+    assert_eq!(
+        statuses(&read_all(&mut client)),
+        ["HTTP/1.1 405 Method Not Allowed"]
+    );
+}
+
+#[test]
+fn route_without_methods_is_refused_before_the_body() {
+    // The same answer as once the body has arrived: such a route accepts no method
+    let routes = HashMap::from([("/".to_string(), RouteConfig::default())]);
+    let server = serve(|server| server.router = Arc::new(Router::new(&routes).unwrap()));
+    for expect in ["Expect: 100-continue\r\n", ""] {
+        let mut client = connect(&server);
+        client
+            .write_all(
+                format!("POST /x HTTP/1.1\r\nHost: a\r\n{expect}Content-Length: 5\r\nConnection: close\r\n\r\n")
+                    .as_bytes(),
+            )
+            .unwrap();
+        if expect.is_empty() {
+            client.write_all(b"hello").unwrap();
+        }
+        assert_eq!(
+            statuses(&read_all(&mut client)),
+            ["HTTP/1.1 400 Bad Request"],
+            "{expect:?}"
+        );
+    }
+}
+
+#[test]
+fn oversized_body_is_refused_before_continue() {
+    let server = serve(|server| server.size_limit = Some(1024));
+    let mut client = connect(&server);
+    client
+        .write_all(b"POST /x HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 1000000\r\n\r\n")
+        .unwrap();
+    assert_eq!(
+        statuses(&read_all(&mut client)),
+        ["HTTP/1.1 413 Payload Too Large"]
+    );
+}
+
+#[test]
+fn oversized_body_is_refused_before_it_is_sent() {
+    let server = serve(|server| server.size_limit = Some(1024));
+    let mut client = connect(&server);
+    // Only the head is sent: the answer must not wait for the body
+    client
+        .write_all(b"POST /x HTTP/1.1\r\nHost: a\r\nContent-Length: 1000000\r\n\r\n")
+        .unwrap();
+    let started = Instant::now();
+    assert_eq!(
+        statuses(&read_all(&mut client)),
+        ["HTTP/1.1 413 Payload Too Large"]
+    );
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+// Timeouts short enough for a test
+fn short_timeouts(server: &mut ServerSetup) {
+    server.timeouts = Timeouts {
+        keepalive: Duration::from_millis(200),
+        header: Duration::from_millis(200),
+        body: Duration::from_millis(200),
+        send: Duration::from_millis(200),
+    };
+}
+
+#[test]
+fn stalled_body_gets_a_408() {
+    let server = serve(short_timeouts);
+    let mut client = connect(&server);
+    client
+        .write_all(b"POST /x HTTP/1.1\r\nHost: a\r\nContent-Length: 50\r\n\r\nabc")
+        .unwrap();
+    assert_eq!(
+        statuses(&read_all(&mut client)),
+        ["HTTP/1.1 408 Request Timeout"]
+    );
+}
+
+#[test]
+fn timeout_answers_with_the_error_page_of_the_server() {
+    let root = TempDir::new("408");
+    fs::write(root.join("408.html"), "too slow").unwrap();
+    let server = serve(|server| {
+        short_timeouts(server);
+        server.root = root.to_string_lossy().into_owned();
+        server.error_pages = Some(HashMap::from([(408, "408.html".to_string())]));
+    });
+    let mut client = connect(&server);
+    client
+        .write_all(b"POST /x HTTP/1.1\r\nHost: a\r\nContent-Length: 50\r\n\r\nabc")
+        .unwrap();
+    let received = read_all(&mut client);
+    assert_eq!(statuses(&received), ["HTTP/1.1 408 Request Timeout"]);
+    assert!(received.ends_with("\r\n\r\ntoo slow"), "{received}");
+}
+
+#[test]
+fn stalled_head_gets_a_408() {
+    let server = serve(short_timeouts);
+    let mut client = connect(&server);
+    client.write_all(b"GET /x HTTP/1.1\r\nHost:").unwrap();
+    assert_eq!(
+        statuses(&read_all(&mut client)),
+        ["HTTP/1.1 408 Request Timeout"]
+    );
+}
+
+#[test]
+fn idle_connection_is_closed_silently() {
+    let server = serve(short_timeouts);
+    let mut client = connect(&server);
+    client
+        .write_all(b"GET /x HTTP/1.1\r\nHost: a\r\n\r\n")
+        .unwrap();
+    let started = Instant::now();
+    assert_eq!(statuses(&read_all(&mut client)), ["HTTP/1.1 404 Not Found"]);
+    // Closed by the keep-alive timeout, well before the read timeout of the client
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+// Serve the shell script `script` as /cgi/script.sh, from a directory of its own
+fn serve_cgi(name: &str, script: &str) -> TestServer {
+    let root = TempDir::new(&format!("cgi-{name}"));
+    fs::create_dir_all(root.join("cgi")).unwrap();
+    fs::write(root.join("cgi/script.sh"), script).unwrap();
+
+    let routes = HashMap::from([(
+        "/cgi".to_string(),
+        RouteConfig {
+            accepted_methods: Some(vec!["GET".to_string()]),
+            cgi: Some("/bin/sh".to_string()),
+            root: Some(root.to_string_lossy().into_owned()),
+            ..RouteConfig::default()
+        },
+    )]);
+    let mut server = serve(|server| server.router = Arc::new(Router::new(&routes).unwrap()));
+    server._root = Some(root);
+    server
+}
+
+// Cookie header of a fresh session, which scripts need to run
+fn session_cookie() -> String {
+    let id = SessionManager::global().lock().unwrap().create_session();
+    format!("Cookie: session_id={id}\r\n")
+}
+
+#[test]
+fn cgi_needs_a_session() {
+    let server = serve_cgi("session", "echo ran\n");
+    let mut client = connect(&server);
+    client
+        .write_all(b"GET /cgi/script.sh HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let received = read_all(&mut client);
+    assert_eq!(statuses(&received), ["HTTP/1.1 302 Found"]);
+    assert!(received.contains("Location: /session\r\n"), "{received}");
+    assert!(!received.contains("ran"), "{received}");
+}
+
+#[test]
+fn cgi_output_is_streamed_in_chunks() {
+    let server = serve_cgi("chunked", "echo hello\nsleep 1\necho world\n");
+    let mut client = connect(&server);
+    client
+        .write_all(
+            format!(
+                "GET /cgi/script.sh HTTP/1.1\r\nHost: a\r\n{}Connection: close\r\n\r\n",
+                session_cookie()
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+    // The first line is out before the script ends
+    let started = Instant::now();
+    let head = read_head(&mut client);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{head}");
+    assert!(!head.contains("Content-Length"), "{head}");
+    assert_eq!(read_until(&mut client, "hello\n\r\n"), "6\r\nhello\n\r\n");
+    assert!(started.elapsed() < Duration::from_millis(500));
+
+    assert_eq!(read_all(&mut client), "6\r\nworld\n\r\n0\r\n\r\n");
+}
+
+#[test]
+fn cgi_output_ends_with_the_connection_for_http_1_0() {
+    let server = serve_cgi("close", "echo hello\necho world\n");
+    let mut client = connect(&server);
+    client
+        .write_all(
+            format!(
+                "GET /cgi/script.sh HTTP/1.0\r\nHost: a\r\n{}Connection: keep-alive\r\n\r\n",
+                session_cookie()
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    let received = read_all(&mut client);
+    let (head, body) = received.split_once("\r\n\r\n").unwrap();
+    assert!(head.contains("Connection: close"), "{head}");
+    assert!(!head.contains("Transfer-Encoding") && !head.contains("Content-Length"));
+    assert_eq!(body, "hello\nworld\n");
+}
+
+// Twice the largest send buffer a socket gets by default (net.ipv4.tcp_wmem), so that
+// the response can't fit in the socket buffers
+const BIG_FILE: usize = 8 << 20;
+
+// A client whose receive buffer is kept small, or it would grow to take a whole
+// BIG_FILE response
+fn connect_slow_reader(server: &TestServer) -> TcpStream {
+    let client = connect(server);
+    let size: libc::c_int = 64 << 10;
+    let res = unsafe {
+        libc::setsockopt(
+            client.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &size as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(res, 0);
+    client
+}
+
+#[test]
+fn reading_stops_while_responses_are_not_consumed() {
+    let root = TempDir::new("backlog");
+    fs::write(root.join("big.bin"), vec![0; BIG_FILE]).unwrap();
+    let server = serve(|server| server.root = root.to_string_lossy().into_owned());
+
+    // A response larger than the socket buffers, which the client never reads
+    let mut client = connect_slow_reader(&server);
+    client
+        .write_all(b"GET /big.bin HTTP/1.1\r\nHost: a\r\n\r\n")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+
+    // Pipelined requests with large bodies pile up in the socket buffers, not in the server
+    let mut request = b"POST /x HTTP/1.1\r\nHost: a\r\nContent-Length: 1048576\r\n\r\n".to_vec();
+    request.resize(request.len() + (1 << 20), b'x');
+    let payload = request.repeat(64);
+    client.set_nonblocking(true).unwrap();
+    let (mut written, mut stalled_since) = (0, Instant::now());
+    while written < payload.len() && stalled_since.elapsed() < Duration::from_millis(300) {
+        match client.write(&payload[written..]) {
+            Ok(n) => {
+                written += n;
+                stalled_since = Instant::now();
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(10))
+            }
+            Err(e) => panic!("{e}"),
+        }
+    }
+    assert!(written < 16 << 20, "{written} bytes accepted");
+}
+
+#[test]
+fn large_file_arrives_intact() {
+    // Bytes that differ from one offset to the next, so that a chunk sent twice or
+    // skipped shows
+    let content: Vec<u8> = (0..BIG_FILE).map(|n| (n % 251) as u8).collect();
+    let root = TempDir::new("sendfile");
+    fs::write(root.join("big.bin"), &content).unwrap();
+    let server = serve(|server| server.root = root.to_string_lossy().into_owned());
+
+    let mut client = connect(&server);
+    client
+        .write_all(b"GET /big.bin HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let received = read_bytes(&mut client);
+    let end = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8_lossy(&received[..end]);
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+    assert!(
+        head.contains(&format!("Content-Length: {BIG_FILE}")),
+        "{head}"
+    );
+    assert!(
+        received[end..] == content,
+        "{} bytes of body",
+        received.len() - end
+    );
+}
+
+#[test]
+fn pipelined_responses_keep_their_order_past_the_queue_limit() {
+    // 150 files, each holding its own number
+    let root = TempDir::new("pipeline");
+    for n in 0..150 {
+        fs::write(root.join(format!("{n}.txt")), format!("<{n}>")).unwrap();
+    }
+    let server = serve(|server| {
+        server.root = root.to_string_lossy().into_owned();
+        server.max_requests = 1000;
+    });
+
+    // More requests at once than the queue holds
+    let mut client = connect(&server);
+    let mut requests = String::new();
+    for n in 0..150 {
+        let connection = if n == 149 { "close" } else { "keep-alive" };
+        requests +=
+            &format!("GET /{n}.txt HTTP/1.1\r\nHost: a\r\nConnection: {connection}\r\n\r\n");
+    }
+    client.write_all(requests.as_bytes()).unwrap();
+
+    let received = read_all(&mut client);
+    assert_eq!(statuses(&received), ["HTTP/1.1 200 OK"; 150]);
+    let bodies: Vec<usize> = received
+        .split('<')
+        .skip(1)
+        .map(|body| body[..body.find('>').unwrap()].parse().unwrap())
+        .collect();
+    assert_eq!(bodies, (0..150).collect::<Vec<_>>());
+}
+
+// Status of the response to `request`, sent alone on a connection to a server with
+// small header limits
+fn status_with_small_limits(request: &str) -> String {
+    let server = serve(|server| {
+        server.header_limits = HeaderLimits {
+            request_line: 64,
+            header_line: 64,
+            total: 256,
+            count: 8,
+        }
+    });
+    let mut client = connect(&server);
+    client.write_all(request.as_bytes()).unwrap();
+    let received = read_all(&mut client);
+    statuses(&received).join(", ")
+}
+
+#[test]
+fn oversized_request_heads_are_refused() {
+    let long_path = "/".repeat(64);
+    let long_value = "x".repeat(64);
+    let headers = |count: usize, len: usize| -> String {
+        (0..count)
+            .map(|n| format!("X-{n}: {}\r\n", "y".repeat(len)))
+            .collect()
+    };
+
+    assert_eq!(
+        status_with_small_limits("GET /x HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n"),
+        "HTTP/1.1 404 Not Found"
+    );
+    assert_eq!(
+        status_with_small_limits(&format!("GET {long_path} HTTP/1.1\r\nHost: a\r\n\r\n")),
+        "HTTP/1.1 414 URI Too Long"
+    );
+    // Refused before the end of the line arrives
+    assert_eq!(
+        status_with_small_limits(&format!("GET {long_path}{long_path}")),
+        "HTTP/1.1 414 URI Too Long"
+    );
+    assert_eq!(
+        status_with_small_limits(&format!(
+            "GET / HTTP/1.1\r\nHost: a\r\nX: {long_value}\r\n\r\n"
+        )),
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+    // 9 lines, one more than allowed
+    assert_eq!(
+        status_with_small_limits(&format!(
+            "GET / HTTP/1.1\r\nHost: a\r\n{}\r\n",
+            headers(8, 1)
+        )),
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+    // 8 lines of about 40 bytes, over the 256 bytes allowed in all
+    assert_eq!(
+        status_with_small_limits(&format!(
+            "GET / HTTP/1.1\r\nHost: a\r\n{}\r\n",
+            headers(7, 32)
+        )),
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+}
+
+#[test]
+fn connection_closes_after_its_last_allowed_request() {
+    let server = serve(|server| server.max_requests = 2);
+    let mut client = connect(&server);
+    client
+        .write_all(
+            b"GET /a HTTP/1.1\r\nHost: a\r\n\r\nGET /b HTTP/1.1\r\nHost: a\r\n\r\nGET /c HTTP/1.1\r\nHost: a\r\n\r\n",
+        )
+        .unwrap();
+    let started = Instant::now();
+    let received = read_all(&mut client);
+    // Closed by the server, not left for the client's read timeout
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    assert_eq!(
+        statuses(&received),
+        ["HTTP/1.1 404 Not Found", "HTTP/1.1 404 Not Found"]
+    );
+    let second = &received[received.rfind("HTTP/1.1 ").unwrap()..];
+    let (first_head, _) = received.split_once("\r\n\r\n").unwrap();
+    let (second_head, _) = second.split_once("\r\n\r\n").unwrap();
+    assert!(
+        first_head.contains("Connection: keep-alive"),
+        "{first_head}"
+    );
+    assert!(first_head.contains("max=1"), "{first_head}");
+    assert!(second_head.contains("Connection: close"), "{second_head}");
+}
+
+#[test]
+fn workers_share_the_port() {
+    // Two workers on the same address, each serving a root that names it
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let roots = ["a", "b"].map(|name| {
+        let root = TempDir::new(&format!("worker-{name}"));
+        fs::write(root.join("who.txt"), name).unwrap();
+        root
+    });
+    let workers = roots.each_ref().map(|root| {
+        serve_on(bind_reuseport(&address).unwrap(), |server| {
+            server.root = root.to_string_lossy().into_owned()
+        })
+    });
+
+    // The kernel spreads new connections between them
+    let mut answered = HashSet::new();
+    for _ in 0..100 {
+        let mut client = connect(&workers[0]);
+        client
+            .write_all(b"GET /who.txt HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let received = read_all(&mut client);
+        let (_, body) = received.split_once("\r\n\r\n").unwrap();
+        answered.insert(body.to_string());
+        if answered.len() == 2 {
+            break;
+        }
+    }
+    assert_eq!(answered, HashSet::from(["a".to_string(), "b".to_string()]));
+
+    // The port stays served by the other one once a worker stops
+    let [first, second] = workers;
+    drop(first);
+    for _ in 0..10 {
+        let mut client = connect(&second);
+        client
+            .write_all(b"GET /who.txt HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        assert!(read_all(&mut client).ends_with("\r\n\r\nb"));
+    }
+}
+
+#[test]
+fn shutdown_finishes_the_response_in_flight() {
+    let root = TempDir::new("drain");
+    fs::write(root.join("big.bin"), vec![0; BIG_FILE]).unwrap();
+    let server = serve(|server| server.root = root.to_string_lossy().into_owned());
+
+    // A keep-alive request whose response is still being sent when shutdown is asked
+    let mut client = connect_slow_reader(&server);
+    client
+        .write_all(b"GET /big.bin HTTP/1.1\r\nHost: a\r\n\r\n")
+        .unwrap();
+    let head = read_head(&mut client);
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+    std::thread::sleep(Duration::from_millis(100));
+    server.controller.send(Control::Shutdown);
+    std::thread::sleep(Duration::from_millis(50));
+
+    // No new connection is accepted, but the response ends, then the connection closes
+    assert!(TcpStream::connect(server.addr).is_err());
+    let started = Instant::now();
+    let body = read_bytes(&mut client);
+    assert_eq!(body.len(), BIG_FILE);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
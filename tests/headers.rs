@@ -0,0 +1,96 @@
+// Header names are case-insensitive, order is kept as received, and repeated
+// headers read as one comma-separated list.
+
+use localhost::headers::Headers;
+
+fn headers(entries: &[(&str, &str)]) -> Headers {
+    entries
+        .iter()
+        .map(|&(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn entries(headers: &Headers) -> Vec<(&str, &str)> {
+    headers.iter().collect()
+}
+
+#[test]
+fn names_are_case_insensitive() {
+    let headers = headers(&[("Content-Type", "text/html"), ("set-cookie", "a=1")]);
+    assert_eq!(headers.get("content-type"), Some("text/html"));
+    assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+    assert_eq!(headers.get("Set-Cookie"), Some("a=1"));
+    assert!(headers.contains("SET-COOKIE"));
+    assert_eq!(headers.get("Content-Length"), None);
+}
+
+#[test]
+fn insert_replaces_every_value_in_place() {
+    let mut headers = headers(&[
+        ("Accept", "text/html"),
+        ("Host", "a"),
+        ("accept", "text/plain"),
+        ("Connection", "close"),
+        ("ACCEPT", "*/*"),
+    ]);
+    headers.insert("accept", "application/json");
+    // The first occurrence keeps its place and name, the others are gone
+    assert_eq!(
+        entries(&headers),
+        [
+            ("Accept", "application/json"),
+            ("Host", "a"),
+            ("Connection", "close")
+        ]
+    );
+
+    headers.insert("Content-Length", "0");
+    assert_eq!(entries(&headers).last(), Some(&("Content-Length", "0")));
+    assert_eq!(headers.len(), 4);
+}
+
+#[test]
+fn order_is_preserved() {
+    let mut headers = headers(&[("Host", "a"), ("Cookie", "a=1"), ("Accept", "*/*")]);
+    headers.append("cookie", "b=2");
+    headers.append("X-Last", "1");
+    assert_eq!(
+        entries(&headers),
+        [
+            ("Host", "a"),
+            ("Cookie", "a=1"),
+            ("Accept", "*/*"),
+            ("cookie", "b=2"),
+            ("X-Last", "1")
+        ]
+    );
+    assert_eq!(
+        headers.get_all("COOKIE").collect::<Vec<_>>(),
+        ["a=1", "b=2"]
+    );
+}
+
+#[test]
+fn get_list_joins_repeated_headers() {
+    let headers = headers(&[
+        ("Accept", "text/html"),
+        ("Host", "a"),
+        ("accept", "text/plain, */*"),
+    ]);
+    assert_eq!(
+        headers.get_list("Accept").as_deref(),
+        Some("text/html, text/plain, */*")
+    );
+    assert_eq!(headers.get_list("Host").as_deref(), Some("a"));
+    assert_eq!(headers.get_list("Cookie"), None);
+}
+
+#[test]
+fn remove_drops_every_value() {
+    let mut headers = headers(&[("Cookie", "a=1"), ("Host", "a"), ("cookie", "b=2")]);
+    assert!(headers.remove("COOKIE"));
+    assert_eq!(entries(&headers), [("Host", "a")]);
+    assert!(!headers.remove("Cookie"));
+    assert!(headers.remove("host"));
+    assert!(headers.is_empty());
+}
@@ -0,0 +1,220 @@
+// Client-supplied paths (request targets, upload filenames, delete requests)
+// must never reach a file outside the directory they are resolved against.
+
+mod common;
+
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use localhost::{
+    delete_file::handle_delete,
+    file_upload::handle_post,
+    headers::Headers,
+    http_request::HttpRequest,
+    http_response::HttpResponse,
+    path_resolver::{normalize, percent_decode, resolve, DocumentRoot, PathError},
+};
+
+use common::TempDir;
+
+// A fresh directory tree for one test: root/inside.txt, root/sub/, and
+// outside.txt next to the root. The tree is removed with the returned TempDir.
+fn sandbox(name: &str) -> (TempDir, PathBuf, PathBuf) {
+    let base = TempDir::new(name);
+    let root = base.join("root");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("inside.txt"), "inside").unwrap();
+    fs::write(base.join("outside.txt"), "outside").unwrap();
+    let outside = base.join("outside.txt");
+    (base, root.canonicalize().unwrap(), outside)
+}
+
+fn request(method: &str, target: &str, headers: Headers, body: &[u8]) -> HttpRequest {
+    let head = format!("{method} {target} HTTP/1.1\r\nHost: a\r\n\r\n");
+    let mut request = HttpRequest::parse_head(head.as_bytes(), -1, -1).unwrap();
+    for (name, value) in headers.iter() {
+        request.headers.append(name, value);
+    }
+    request.body = body.to_vec();
+    request
+}
+
+#[test]
+fn dot_segments_inside_the_root_are_resolved() {
+    let (_base, root, _) = sandbox("inside");
+    for path in [
+        "/inside.txt",
+        "/sub/../inside.txt",
+        "/./sub/./../inside.txt",
+        "//inside.txt",
+    ] {
+        assert_eq!(resolve(&root, path), Ok(root.join("inside.txt")), "{path}");
+    }
+}
+
+#[test]
+fn dot_dot_escaping_the_root_is_forbidden() {
+    let (_base, root, _) = sandbox("dotdot");
+    for path in [
+        "/../outside.txt",
+        "../outside.txt",
+        "/sub/../../outside.txt",
+        "/sub/../..",
+    ] {
+        assert_eq!(resolve(&root, path), Err(PathError::Forbidden), "{path}");
+    }
+}
+
+#[test]
+fn backslashes_are_separators() {
+    let (_base, root, _) = sandbox("backslash");
+    assert_eq!(resolve(&root, "..\\outside.txt"), Err(PathError::Forbidden));
+    assert_eq!(
+        resolve(&root, "sub\\..\\inside.txt"),
+        Ok(root.join("inside.txt"))
+    );
+}
+
+#[test]
+fn absolute_paths_stay_under_the_root() {
+    let (_base, root, outside) = sandbox("absolute");
+    let resolved = resolve(&root, outside.to_str().unwrap()).unwrap();
+    assert!(resolved.starts_with(&root), "{}", resolved.display());
+    assert_eq!(resolve(&root, "/etc/passwd"), Ok(root.join("etc/passwd")));
+}
+
+#[test]
+fn nul_bytes_are_invalid() {
+    let (_base, root, _) = sandbox("nul");
+    assert_eq!(resolve(&root, "/inside.txt\0.png"), Err(PathError::Invalid));
+    assert_eq!(normalize("a\0b"), Err(PathError::Invalid));
+}
+
+#[test]
+fn symlinks_out_of_the_root_are_forbidden() {
+    let (_base, root, outside) = sandbox("symlink");
+    symlink(&outside, root.join("link.txt")).unwrap();
+    symlink(outside.parent().unwrap(), root.join("sub/up")).unwrap();
+    symlink(root.join("missing"), root.join("dangling")).unwrap();
+    symlink(root.join("inside.txt"), root.join("sub/ok.txt")).unwrap();
+
+    assert_eq!(resolve(&root, "/link.txt"), Err(PathError::Forbidden));
+    assert_eq!(
+        resolve(&root, "/sub/up/outside.txt"),
+        Err(PathError::Forbidden)
+    );
+    assert_eq!(resolve(&root, "/dangling"), Err(PathError::Forbidden));
+    assert_eq!(resolve(&root, "/sub/ok.txt"), Ok(root.join("inside.txt")));
+}
+
+#[test]
+fn missing_files_resolve_for_creation() {
+    let (_base, root, _) = sandbox("missing");
+    assert_eq!(
+        resolve(&root, "/sub/new/file.txt"),
+        Ok(root.join("sub/new/file.txt"))
+    );
+    assert_eq!(
+        resolve(&root.join("nope"), "/file.txt"),
+        Err(PathError::NotFound)
+    );
+}
+
+#[test]
+fn paths_are_decoded_only_once() {
+    assert_eq!(percent_decode("/%2e%2e/%2E%2E/etc").unwrap(), "/../../etc");
+    assert_eq!(percent_decode("/%252e%252e").unwrap(), "/%2e%2e");
+    assert_eq!(percent_decode("/%ff"), Err(PathError::Invalid));
+
+    // A double-encoded ".." is a plain file name once the request is parsed
+    let (_base, root, _) = sandbox("double");
+    let path = request("GET", "/%252e%252e/outside.txt", Headers::new(), b"").path;
+    assert_eq!(resolve(&root, &path), Ok(root.join("%2e%2e/outside.txt")));
+}
+
+#[test]
+fn alias_replaces_the_route_path() {
+    let (_base, root, _) = sandbox("alias");
+    let alias = DocumentRoot::alias(&root, "/assets/");
+    assert_eq!(
+        alias.resolve("/assets/inside.txt"),
+        Ok(root.join("inside.txt"))
+    );
+    assert_eq!(alias.resolve("/assets"), Ok(root.clone()));
+    assert_eq!(
+        alias.resolve("/assets/../outside.txt"),
+        Err(PathError::Forbidden)
+    );
+    assert_eq!(
+        alias.resolve("/assets/sub/../../outside.txt"),
+        Err(PathError::Forbidden)
+    );
+}
+
+#[test]
+fn static_files_outside_public_are_forbidden() {
+    let public = DocumentRoot::root("public");
+    for target in [
+        "/../Cargo.toml",
+        "/%2e%2e/Cargo.toml",
+        "/%2E%2E%2FCargo.toml",
+        "/..%5cCargo.toml",
+    ] {
+        let response =
+            HttpResponse::get_static(request("GET", target, Headers::new(), b""), &public, None);
+        assert_eq!(response.status_code, 403, "{target}");
+    }
+    let response = HttpResponse::get_static(
+        request("GET", "/style.css%00.png", Headers::new(), b""),
+        &public,
+        None,
+    );
+    assert_eq!(response.status_code, 400);
+    let response = HttpResponse::get_static(
+        request("GET", "/sub/../style.css", Headers::new(), b""),
+        &public,
+        None,
+    );
+    assert_eq!(response.status_code, 200);
+}
+
+#[test]
+fn delete_outside_the_upload_directory_is_forbidden() {
+    let (_base, _, outside) = sandbox("delete");
+    let escape = format!("../../../../../../../../..{}", outside.display());
+    for path in [escape.as_str(), "../../Cargo.toml", "../index.html"] {
+        let body = serde_json::json!({ "path": path }).to_string();
+        let response = handle_delete(
+            request("DELETE", "/delete", Headers::new(), body.as_bytes()),
+            &DocumentRoot::root("public"),
+            None,
+        );
+        assert_eq!(response.status_code, 403, "{path}");
+    }
+    assert!(outside.exists());
+    assert!(Path::new("Cargo.toml").exists());
+}
+
+#[test]
+fn upload_filename_outside_the_upload_directory_is_forbidden() {
+    for filename in ["../../target/escape.txt", "..\\..\\target\\escape.txt"] {
+        let body = format!(
+            "--XYZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\
+             Content-Type: text/plain\r\n\r\nescaped\r\n--XYZ--\r\n"
+        );
+        let headers = Headers::from(vec![(
+            "Content-Type".to_string(),
+            "multipart/form-data; boundary=XYZ".to_string(),
+        )]);
+        let response = handle_post(
+            request("POST", "/upload", headers, body.as_bytes()),
+            &DocumentRoot::root("public"),
+            None,
+        );
+        assert_eq!(response.status_code, 403, "{filename}");
+    }
+    assert!(!Path::new("target/escape.txt").exists());
+}
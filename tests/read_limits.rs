@@ -0,0 +1,106 @@
+// However much a client has sent, the read buffer holds no more than the request
+// being read may need: a head at the largest the limits allow, and a body a buffer
+// at a time.
+
+use std::{
+    io::{ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use localhost::connection::{
+    Connection, HeaderLimits, ParseOutcome, Timeouts, DEFAULT_MAX_REQUESTS,
+};
+
+// A server-side connection with `payload` waiting in its socket, as much of it as the
+// socket buffers take, and the client end
+fn connection_with(payload: &[u8]) -> (Connection, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    stream.set_nonblocking(true).unwrap();
+    client.set_nonblocking(true).unwrap();
+    let mut written = 0;
+    while written < payload.len() {
+        match client.write(&payload[written..]) {
+            Ok(n) => written += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => panic!("{e}"),
+        }
+    }
+    assert!(written > 1 << 20, "only {written} bytes were sent");
+    let connection = Connection::new(stream, -1, Timeouts::default(), DEFAULT_MAX_REQUESTS);
+    (connection, client)
+}
+
+#[test]
+fn a_head_is_read_no_further_than_its_limits() {
+    let mut head = b"GET / HTTP/1.1\r\nHost: a\r\nX-Big: ".to_vec();
+    head.resize(16 << 20, b'x');
+    let (mut connection, _client) = connection_with(&head);
+
+    connection.fill_read_buf().unwrap();
+    assert!(connection.read_buf.len() <= HeaderLimits::default().max_head());
+    assert!(matches!(
+        connection.next_request(),
+        ParseOutcome::HeadersTooLarge
+    ));
+}
+
+#[test]
+fn a_head_that_never_ends_is_too_large() {
+    // Bare line feeds: empty lines, within every limit, that never end the head
+    let mut head = b"GET / HTTP/1.1\r\nHost: a\r\n".to_vec();
+    head.extend(b"\n".repeat(16 << 20));
+    let (mut connection, _client) = connection_with(&head);
+
+    connection.fill_read_buf().unwrap();
+    assert!(matches!(
+        connection.next_request(),
+        ParseOutcome::HeadersTooLarge
+    ));
+}
+
+#[test]
+fn a_body_is_read_a_buffer_at_a_time() {
+    let mut request = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 33554432\r\n\r\n".to_vec();
+    request.resize(16 << 20, b'x');
+    let (mut connection, _client) = connection_with(&request);
+    let buffer = HeaderLimits::default().max_head();
+
+    connection.fill_read_buf().unwrap();
+    assert!(matches!(connection.next_request(), ParseOutcome::Headers));
+    for _ in 0..4 {
+        connection.fill_read_buf().unwrap();
+        assert!(
+            connection.read_buf.len() <= buffer,
+            "{} bytes buffered",
+            connection.read_buf.len()
+        );
+        assert!(matches!(
+            connection.next_request(),
+            ParseOutcome::Incomplete
+        ));
+    }
+}
+
+#[test]
+fn nothing_is_read_past_the_body() {
+    // A body longer than one buffer, followed by more than the socket buffers hold
+    let mut request = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 100000\r\n\r\n".to_vec();
+    request.resize(request.len() + 100_000, b'x');
+    request.resize(request.len() + (16 << 20), b'?');
+    let (mut connection, _client) = connection_with(&request);
+
+    connection.fill_read_buf().unwrap();
+    assert!(matches!(connection.next_request(), ParseOutcome::Headers));
+    let request = loop {
+        connection.fill_read_buf().unwrap();
+        match connection.next_request() {
+            ParseOutcome::Incomplete => continue,
+            ParseOutcome::Complete(request) => break request,
+            outcome => panic!("{outcome:?}"),
+        }
+    };
+    assert_eq!(request.body, vec![b'x'; 100_000]);
+    assert!(connection.read_buf.is_empty());
+}
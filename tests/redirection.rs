@@ -0,0 +1,137 @@
+// Redirect targets are filled in from the request path and the route parameters,
+// optionally carry the query on, and use the status of the route.
+
+use std::collections::HashMap;
+
+use localhost::{
+    cgi::handle_redirection, config::RouteConfig, http_request::HttpRequest, router::Router,
+};
+
+fn redirect(target: &str, status: Option<u16>, query: bool) -> RouteConfig {
+    RouteConfig {
+        redirection: Some(target.to_string()),
+        redirect_status: status,
+        redirect_query: Some(query),
+        ..RouteConfig::default()
+    }
+}
+
+// Status and Location of the response to a GET of `target` on `routes`
+fn follow(routes: &[(&str, RouteConfig)], target: &str) -> (u16, String) {
+    let routes: HashMap<String, RouteConfig> = routes
+        .iter()
+        .map(|(key, config)| (key.to_string(), config.clone()))
+        .collect();
+    let router = Router::new(&routes).unwrap();
+    let head = format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n");
+    let mut request = HttpRequest::parse_head(head.as_bytes(), -1, -1).unwrap();
+    let route = router.find(&request.path).unwrap();
+    request.params = route.params.clone();
+
+    let response = handle_redirection(&route, &request).unwrap();
+    let location = response.headers.get("Location").unwrap().to_string();
+    (response.status_code, location)
+}
+
+#[test]
+fn targets_are_filled_in_from_the_request() {
+    let cases = [
+        ("/old", "/new$rest", "/old", "/new"),
+        ("/old", "/new$rest", "/old/a/b.html", "/new/a/b.html"),
+        (
+            "/old",
+            "https://example.com${rest}x",
+            "/old/a",
+            "https://example.com/ax",
+        ),
+        ("/users/:id", "/profiles/$id/", "/users/42", "/profiles/42/"),
+        ("/users/:id", "/p/${id}_$id", "/users/7", "/p/7_7"),
+        (
+            "/files/*path",
+            "/static/$path",
+            "/files/css/app.css",
+            "/static/css/app.css",
+        ),
+        (
+            "~ ^/blog/(\\d+)/(\\w+)$",
+            "/posts/$2-$1",
+            "/blog/2024/hello",
+            "/posts/hello-2024",
+        ),
+        (
+            "~ ^/v(?<version>\\d)/(.*)$",
+            "/api/$2?v=$version",
+            "/v2/items",
+            "/api/items?v=2",
+        ),
+        // A group that took no part in the match
+        ("~ ^/a(/b)?$", "/z$1", "/a", "/z"),
+        // A '$' that starts no variable stays as written
+        ("/cost", "/price/$/${", "/cost", "/price/$/${"),
+        ("/cost", "/price/${rest", "/cost", "/price/${rest"),
+        // Values are encoded again for the Location header
+        ("/old", "/new$rest", "/old/a%20b", "/new/a%20b"),
+    ];
+    for (key, template, path, location) in cases {
+        let routes = [(key, redirect(template, None, false))];
+        assert_eq!(
+            follow(&routes, path),
+            (301, location.to_string()),
+            "{key} -> {template} for {path}"
+        );
+    }
+}
+
+#[test]
+fn query_is_forwarded_on_request() {
+    let cases = [
+        ("/new", false, "/old?q=1", "/new"),
+        ("/new", true, "/old", "/new"),
+        ("/new", true, "/old?q=1&r=2", "/new?q=1&r=2"),
+        ("/new?lang=en", true, "/old?q=1", "/new?lang=en&q=1"),
+        ("/new?lang=en", false, "/old?q=1", "/new?lang=en"),
+    ];
+    for (template, query, target, location) in cases {
+        let routes = [("/old", redirect(template, None, query))];
+        assert_eq!(
+            follow(&routes, target).1,
+            location,
+            "{template} for {target}"
+        );
+    }
+}
+
+#[test]
+fn status_comes_from_the_route() {
+    for status in [301, 302, 303, 307, 308] {
+        let routes = [("/old", redirect("/new", Some(status), false))];
+        assert_eq!(follow(&routes, "/old"), (status, "/new".to_string()));
+    }
+    let routes = [("/old", redirect("/new", None, false))];
+    assert_eq!(follow(&routes, "/old").0, 301);
+}
+
+#[test]
+fn unknown_variables_are_refused() {
+    let cases = [
+        ("/old", "/new/$id"),
+        ("/old", "/new/${path}"),
+        ("/users/:id", "/p/$name"),
+        ("~ ^/blog/(\\d+)$", "/posts/$2"),
+        ("~ ^/v(?<version>\\d)$", "/api/$1"),
+    ];
+    for (key, template) in cases {
+        let routes = HashMap::from([(key.to_string(), redirect(template, None, false))]);
+        let err = Router::new(&routes).unwrap_err();
+        assert!(err.contains("redirection"), "{key} -> {template}: {err}");
+    }
+}
+
+#[test]
+fn statuses_other_than_redirects_are_refused() {
+    for status in [200, 300, 304, 305, 404] {
+        let routes = HashMap::from([("/old".to_string(), redirect("/new", Some(status), false))]);
+        let err = Router::new(&routes).unwrap_err();
+        assert!(err.contains("redirect_status"), "{status}: {err}");
+    }
+}
@@ -0,0 +1,97 @@
+// The request line and header lines follow RFC 9112 strictly: anything a lenient
+// parser would have to guess about is refused.
+
+use localhost::http_request::{HttpRequest, RequestError};
+
+// What parsing `head` gives: Ok with the Host header, or the error
+fn parse(head: &str) -> Result<String, RequestError> {
+    let request = HttpRequest::parse_head(head.as_bytes(), -1, -1)?;
+    Ok(request.headers.get("Host").unwrap_or_default().to_string())
+}
+
+#[test]
+fn request_heads_are_parsed_strictly() {
+    use RequestError::{BadRequest, VersionNotSupported};
+    let ok = |host: &str| Ok(host.to_string());
+    let cases = [
+        ("GET / HTTP/1.1\r\nHost: a\r\n\r\n", ok("a")),
+        ("GET / HTTP/1.0\r\n\r\n", ok("")),
+        // Optional whitespace around the value
+        ("GET / HTTP/1.1\r\nHost:example\r\n\r\n", ok("example")),
+        (
+            "GET / HTTP/1.1\r\nHost: \texample \t\r\n\r\n",
+            ok("example"),
+        ),
+        // Versions
+        (
+            "GET / HTTP/2.0\r\nHost: a\r\n\r\n",
+            Err(VersionNotSupported),
+        ),
+        (
+            "GET / HTTP/0.9\r\nHost: a\r\n\r\n",
+            Err(VersionNotSupported),
+        ),
+        ("GET / HTTP/1.10\r\nHost: a\r\n\r\n", Err(BadRequest)),
+        ("GET / http/1.1\r\nHost: a\r\n\r\n", Err(BadRequest)),
+        ("GET /\r\nHost: a\r\n\r\n", Err(BadRequest)),
+        // Request line
+        ("GE(T / HTTP/1.1\r\nHost: a\r\n\r\n", Err(BadRequest)),
+        ("G\"ET / HTTP/1.1\r\nHost: a\r\n\r\n", Err(BadRequest)),
+        (" GET / HTTP/1.1\r\nHost: a\r\n\r\n", Err(BadRequest)),
+        ("GET  / HTTP/1.1\r\nHost: a\r\n\r\n", Err(BadRequest)),
+        ("GET /a\tb HTTP/1.1\r\nHost: a\r\n\r\n", Err(BadRequest)),
+        // Header names
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nX(Y): 1\r\n\r\n",
+            Err(BadRequest),
+        ),
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nX Y: 1\r\n\r\n",
+            Err(BadRequest),
+        ),
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nX-Y : 1\r\n\r\n",
+            Err(BadRequest),
+        ),
+        ("GET / HTTP/1.1\r\nHost: a\r\n: 1\r\n\r\n", Err(BadRequest)),
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nNoColon\r\n\r\n",
+            Err(BadRequest),
+        ),
+        // Obsolete line folding
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nX-Y: 1\r\n 2\r\n\r\n",
+            Err(BadRequest),
+        ),
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nX-Y: 1\r\n\t2\r\n\r\n",
+            Err(BadRequest),
+        ),
+        // Control characters in a value
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nX-Y: 1\x002\r\n\r\n",
+            Err(BadRequest),
+        ),
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nX-Y: 1\n2\r\n\r\n",
+            Err(BadRequest),
+        ),
+        // Host
+        ("GET / HTTP/1.1\r\n\r\n", Err(BadRequest)),
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+            Err(BadRequest),
+        ),
+        (
+            "GET / HTTP/1.1\r\nHost: a\r\nhost: a\r\n\r\n",
+            Err(BadRequest),
+        ),
+        (
+            "GET / HTTP/1.0\r\nHost: a\r\nHost: b\r\n\r\n",
+            Err(BadRequest),
+        ),
+    ];
+    for (head, expected) in cases {
+        assert_eq!(parse(head), expected, "{head:?}");
+    }
+}
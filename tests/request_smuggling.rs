@@ -0,0 +1,196 @@
+// Requests whose framing could be read differently by a proxy in front of the
+// server must be refused, and well-framed bodies must never leak into the
+// next request.
+
+use std::{
+    io::Write,
+    net::{Shutdown, TcpListener, TcpStream},
+    os::fd::AsRawFd,
+};
+
+use localhost::connection::{Connection, ParseOutcome, Timeouts, DEFAULT_MAX_REQUESTS};
+
+// Feed `payload` to a server-side connection, as if it arrived from a client,
+// and describe every request parsed out of it, stopping at the first error
+fn parse(payload: &[u8]) -> Vec<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+
+    client.write_all(payload).unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+
+    let mut connection = Connection::new(
+        stream,
+        listener.as_raw_fd(),
+        Timeouts::default(),
+        DEFAULT_MAX_REQUESTS,
+    );
+    connection.fill_read_buf().unwrap();
+
+    let mut parsed = Vec::new();
+    loop {
+        match connection.next_request() {
+            ParseOutcome::Headers => continue,
+            ParseOutcome::Complete(request) => parsed.push(format!(
+                "{} {} {:?}",
+                request.method,
+                request.path,
+                String::from_utf8_lossy(&request.body)
+            )),
+            ParseOutcome::Incomplete => break,
+            error => {
+                parsed.push(format!("{:?}", error));
+                break;
+            }
+        }
+    }
+    parsed
+}
+
+#[test]
+fn content_length_with_chunked_is_refused() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nX";
+    assert_eq!(parse(payload), ["Invalid"]);
+}
+
+#[test]
+fn chunked_with_content_length_is_refused() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n8\r\nSMUGGLED\r\n0\r\n\r\n";
+    assert_eq!(parse(payload), ["Invalid"]);
+}
+
+#[test]
+fn differing_content_lengths_are_refused() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\nContent-Length: 23\r\n\r\nGET /admin HTTP/1.1\r\n\r\n";
+    assert_eq!(parse(payload), ["Invalid"]);
+}
+
+#[test]
+fn content_length_list_with_differing_values_is_refused() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3, 4\r\n\r\nabcd";
+    assert_eq!(parse(payload), ["Invalid"]);
+}
+
+#[test]
+fn repeated_identical_content_length_is_accepted() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabcGET /next HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert_eq!(parse(payload), ["POST / \"abc\"", "GET /next \"\""]);
+}
+
+#[test]
+fn malformed_content_length_is_refused() {
+    for value in [
+        "+3",
+        "-1",
+        "0x3",
+        "3 3",
+        "3a",
+        "",
+        "99999999999999999999999",
+    ] {
+        let payload = format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {value}\r\n\r\nabc");
+        assert_eq!(
+            parse(payload.as_bytes()),
+            ["Invalid"],
+            "Content-Length: {value}"
+        );
+    }
+}
+
+#[test]
+fn unsupported_transfer_codings_are_refused() {
+    for value in [
+        "gzip, chunked",
+        "chunked, chunked",
+        "xchunked",
+        "chunked-false",
+        "identity",
+    ] {
+        let payload =
+            format!("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: {value}\r\n\r\n0\r\n\r\n");
+        assert_eq!(
+            parse(payload.as_bytes()),
+            ["Invalid"],
+            "Transfer-Encoding: {value}"
+        );
+    }
+}
+
+#[test]
+fn repeated_transfer_encoding_is_refused() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: identity\r\n\r\n0\r\n\r\n";
+    assert_eq!(parse(payload), ["Invalid"]);
+}
+
+#[test]
+fn transfer_encoding_in_http_1_0_is_refused() {
+    let payload = b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+    assert_eq!(parse(payload), ["Invalid"]);
+}
+
+#[test]
+fn obfuscated_transfer_encoding_is_refused() {
+    let payloads: [&[u8]; 4] = [
+        // Whitespace between the name and the colon
+        b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding : chunked\r\nContent-Length: 4\r\n\r\n0\r\n\r\n",
+        // Obsolete line folding
+        b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding:\r\n chunked\r\nContent-Length: 4\r\n\r\n0\r\n\r\n",
+        // Control character in the value
+        b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: \x0bchunked\r\n\r\n0\r\n\r\n",
+        // Bare LF hiding a header inside another one
+        b"POST / HTTP/1.1\r\nHost: a\r\nX-Foo: bar\nTransfer-Encoding: chunked\r\nContent-Length: 4\r\n\r\n0\r\n\r\n",
+    ];
+    for payload in payloads {
+        assert_eq!(
+            parse(payload),
+            ["Invalid"],
+            "{:?}",
+            String::from_utf8_lossy(payload)
+        );
+    }
+}
+
+#[test]
+fn transfer_encoding_name_is_case_insensitive() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\ntransfer-encoding: CHUNKED\r\ncontent-length: 4\r\n\r\n0\r\n\r\n";
+    assert_eq!(parse(payload), ["Invalid"]);
+}
+
+#[test]
+fn malformed_chunk_sizes_are_refused() {
+    for size in ["0x5", "-5", "+5", "5 5", "", "fffffffffffffffffff"] {
+        let payload = format!(
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n{size}\r\nhello\r\n0\r\n\r\n"
+        );
+        assert_eq!(
+            parse(payload.as_bytes()),
+            ["Invalid"],
+            "chunk size {size:?}"
+        );
+    }
+}
+
+#[test]
+fn chunk_longer_than_its_size_is_refused() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcGET /admin HTTP/1.1\r\n\r\n0\r\n\r\n";
+    assert_eq!(parse(payload), ["Invalid"]);
+}
+
+#[test]
+fn chunked_body_does_not_leak_into_next_request() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n0\r\nX-Trailer: 1\r\n\r\nGET /next HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert_eq!(parse(payload), ["POST / \"hello\"", "GET /next \"\""]);
+}
+
+#[test]
+fn request_inside_a_body_stays_in_the_body() {
+    let payload = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 23\r\n\r\nGET /admin HTTP/1.1\r\n\r\nGET /next HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert_eq!(
+        parse(payload),
+        [
+            "POST / \"GET /admin HTTP/1.1\\r\\n\\r\\n\"",
+            "GET /next \"\""
+        ]
+    );
+}
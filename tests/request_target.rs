@@ -0,0 +1,123 @@
+// The request target is split into a decoded path and a raw query, whatever its
+// form; query strings and form bodies decode into ordered name/value pairs.
+
+use localhost::http_request::{parse_urlencoded, HttpRequest};
+
+fn request(target: &str, headers: &str, body: &str) -> Option<HttpRequest> {
+    let head = format!("POST {target} HTTP/1.1\r\nHost: a\r\n{headers}\r\n");
+    let mut request = HttpRequest::parse_head(head.as_bytes(), -1, -1).ok()?;
+    request.body = body.as_bytes().to_vec();
+    Some(request)
+}
+
+// Path and query of `target`, None if it is refused
+fn split(target: &str) -> Option<(String, Option<String>)> {
+    request(target, "", "").map(|request| (request.path, request.query))
+}
+
+fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|&(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+// Path and query expected for a target, None if it must be refused
+type Split<'a> = Option<(&'a str, Option<&'a str>)>;
+
+#[test]
+fn targets_split_into_path_and_query() {
+    let cases: [(&str, Split); 20] = [
+        // Origin form
+        ("/", Some(("/", None))),
+        ("/a/b.html", Some(("/a/b.html", None))),
+        ("/search?q=1&r=2", Some(("/search", Some("q=1&r=2")))),
+        ("/search?", Some(("/search", Some("")))),
+        ("/a?b?c", Some(("/a", Some("b?c")))),
+        // The path is decoded, the query is kept raw
+        ("/a%20b/c+d?x=%20+y", Some(("/a b/c+d", Some("x=%20+y")))),
+        ("/caf%C3%A9", Some(("/café", None))),
+        ("/%2e%2e/x", Some(("/../x", None))),
+        // Fragments are dropped
+        ("/page#top", Some(("/page", None))),
+        ("/page?q=1#top", Some(("/page", Some("q=1")))),
+        ("/page#top?q=1", Some(("/page", None))),
+        // Absolute form, as sent to proxies
+        ("http://example.com/a/b?q=1", Some(("/a/b", Some("q=1")))),
+        ("HTTPS://example.com:8443/a", Some(("/a", None))),
+        ("http://example.com", Some(("/", None))),
+        ("http://example.com?q=1", Some(("/", Some("q=1")))),
+        ("http://example.com#top", Some(("/", None))),
+        ("*", Some(("*", None))),
+        // Neither origin nor absolute form
+        ("a/b", None),
+        ("ftp://example.com/a", None),
+        // Not UTF-8 once decoded
+        ("/%ff", None),
+    ];
+    for (target, expected) in cases {
+        let expected = expected.map(|(path, query)| (path.to_string(), query.map(str::to_string)));
+        assert_eq!(split(target), expected, "{target}");
+    }
+}
+
+#[test]
+fn urlencoded_pairs_are_decoded_in_order() {
+    let cases: [(&str, &[(&str, &str)]); 9] = [
+        ("", &[]),
+        ("a=1&b=2", &[("a", "1"), ("b", "2")]),
+        ("b=2&a=1&b=3", &[("b", "2"), ("a", "1"), ("b", "3")]),
+        (
+            "q=x+y&r=x%20y&s=x%2By",
+            &[("q", "x y"), ("r", "x y"), ("s", "x+y")],
+        ),
+        ("first+name=Ada", &[("first name", "Ada")]),
+        (
+            "flag&empty=&=nameless",
+            &[("flag", ""), ("empty", ""), ("", "nameless")],
+        ),
+        ("a=1&&b=2&", &[("a", "1"), ("b", "2")]),
+        ("eq=a%3Db=c", &[("eq", "a=b=c")]),
+        (
+            "name=caf%C3%A9&bad=%ff",
+            &[("name", "café"), ("bad", "\u{fffd}")],
+        ),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(parse_urlencoded(input), pairs(expected), "{input}");
+    }
+}
+
+#[test]
+fn query_params_come_from_the_raw_query() {
+    let request = request("/s?q=a+b%26c&page=2#frag", "", "").unwrap();
+    assert_eq!(
+        request.query_params(),
+        pairs(&[("q", "a b&c"), ("page", "2")])
+    );
+    assert_eq!(request.query_param("page").as_deref(), Some("2"));
+    assert_eq!(request.query_param("missing"), None);
+}
+
+#[test]
+fn form_params_need_a_urlencoded_body() {
+    let body = "user=ada+lovelace&lang=en%2Dgb";
+    let cases = [
+        ("Content-Type: application/x-www-form-urlencoded\r\n", true),
+        (
+            "Content-Type: Application/X-WWW-Form-Urlencoded; charset=UTF-8\r\n",
+            true,
+        ),
+        ("Content-Type: multipart/form-data; boundary=x\r\n", false),
+        ("Content-Type: text/plain\r\n", false),
+        ("", false),
+    ];
+    for (header, urlencoded) in cases {
+        let expected = urlencoded.then(|| pairs(&[("user", "ada lovelace"), ("lang", "en-gb")]));
+        assert_eq!(
+            request("/form", header, body).unwrap().form_params(),
+            expected,
+            "{header}"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+// The route chosen for a path follows the documented precedence: exact, then a
+// prefix route equal to the path, then patterns, then regexes, then the longest
+// prefix; rewrite rules send a request on to another route.
+
+use std::collections::HashMap;
+
+use localhost::{
+    config::{RewriteRule, RouteConfig},
+    http_request::HttpRequest,
+    path_resolver::{normalize_path, PathError},
+    router::Router,
+};
+
+fn router(keys: &[&str]) -> Router {
+    let routes: HashMap<String, RouteConfig> = keys
+        .iter()
+        .map(|key| (key.to_string(), RouteConfig::default()))
+        .collect();
+    Router::new(&routes).unwrap()
+}
+
+// Key of the route chosen for `path`, and whether it is the route's own path
+fn route(router: &Router, path: &str) -> Option<(String, bool)> {
+    router
+        .find(path)
+        .map(|route| (route.key.to_string(), route.own_path))
+}
+
+#[test]
+fn longest_prefix_wins_on_segment_boundaries() {
+    let router = router(&["/", "/static", "/static/css"]);
+    assert_eq!(
+        route(&router, "/static/css/app.css"),
+        Some(("/static/css".into(), false))
+    );
+    assert_eq!(
+        route(&router, "/static/app.css"),
+        Some(("/static".into(), false))
+    );
+    assert_eq!(route(&router, "/static"), Some(("/static".into(), true)));
+    assert_eq!(route(&router, "/staticfile"), Some(("/".into(), false)));
+    assert_eq!(route(&router, "/"), Some(("/".into(), true)));
+}
+
+#[test]
+fn dot_segments_are_removed_before_routing() {
+    let router = router(&["/", "/cgi-bin", "/static"]);
+    for target in [
+        "/x/../cgi-bin/script.py",
+        "/x%2F..%2Fcgi-bin/script.py",
+        "/static/./..\\cgi-bin/script.py",
+    ] {
+        let head = format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n");
+        let path = HttpRequest::parse_head(head.as_bytes(), -1, -1)
+            .unwrap()
+            .path;
+        let path = normalize_path(&path).unwrap();
+        assert_eq!(path, "/cgi-bin/script.py", "{target}");
+        assert_eq!(route(&router, &path).unwrap().0, "/cgi-bin", "{target}");
+    }
+    assert_eq!(normalize_path("/static/css/.."), Ok("/static/".into()));
+    assert_eq!(normalize_path("/static/."), Ok("/static/".into()));
+    assert_eq!(normalize_path("/x/.."), Ok("/".into()));
+    assert_eq!(normalize_path("/../etc"), Err(PathError::Forbidden));
+    assert_eq!(normalize_path("/a\0b"), Err(PathError::Invalid));
+}
+
+#[test]
+fn rewrites_cannot_bring_dot_segments_back() {
+    let routes = HashMap::from([
+        (
+            "/a".to_string(),
+            rewriting(&[(r"^/a/(.*)b$", "/static/$1/cgi-bin/x.py", Some("last"))]),
+        ),
+        ("/static".to_string(), RouteConfig::default()),
+        ("/cgi-bin".to_string(), RouteConfig::default()),
+    ]);
+    let router = Router::new(&routes).unwrap();
+    assert_eq!(
+        rewrite(&router, "/a/..b"),
+        Ok(Some(("/cgi-bin".into(), "/cgi-bin/x.py".into())))
+    );
+    assert!(rewrite(&router, "/a/../..b").is_err());
+}
+
+#[test]
+fn exact_routes_match_only_their_path() {
+    let router = router(&["= /login", "/static"]);
+    assert_eq!(route(&router, "/login"), Some(("= /login".into(), true)));
+    assert_eq!(route(&router, "/login/x"), None);
+    assert_eq!(
+        route(&router, "/static/login"),
+        Some(("/static".into(), false))
+    );
+}
+
+#[test]
+fn regex_routes_beat_prefixes_but_not_exact_paths() {
+    let router = router(&["/", "/scripts", "~ \\.py$", "= /run.py"]);
+    assert_eq!(
+        route(&router, "/scripts/a.py"),
+        Some(("~ \\.py$".into(), false))
+    );
+    assert_eq!(route(&router, "/run.py"), Some(("= /run.py".into(), true)));
+    assert_eq!(route(&router, "/scripts"), Some(("/scripts".into(), true)));
+    assert_eq!(
+        route(&router, "/scripts/a.sh"),
+        Some(("/scripts".into(), false))
+    );
+}
+
+#[test]
+fn longest_regex_is_tried_first() {
+    let router = router(&["~ \\.py$", "~ ^/admin/.*\\.py$"]);
+    assert_eq!(
+        route(&router, "/admin/a.py").unwrap().0,
+        "~ ^/admin/.*\\.py$"
+    );
+    assert_eq!(route(&router, "/b.py").unwrap().0, "~ \\.py$");
+}
+
+#[test]
+fn regex_routes_can_ignore_case_and_capture() {
+    let router = router(&["~* ^/users/(?P<id>[0-9]+)/(\\w+)\\.JPG$"]);
+    let found = router.find("/users/42/avatar.jpg").unwrap();
+    assert_eq!(
+        found.params,
+        [
+            ("id".to_string(), "42".to_string()),
+            ("2".to_string(), "avatar".to_string())
+        ]
+    );
+    assert!(router.find("/users/x/avatar.jpg").is_none());
+}
+
+#[test]
+fn invalid_regex_is_refused() {
+    let routes = HashMap::from([("~ (".to_string(), RouteConfig::default())]);
+    assert!(Router::new(&routes).is_err());
+}
+
+#[test]
+fn pattern_parameters_are_captured() {
+    let router = router(&["/users/:id/avatar", "/files/*rest"]);
+    let found = router.find("/users/42/avatar").unwrap();
+    assert_eq!(found.params, [("id".to_string(), "42".to_string())]);
+    assert!(found.own_path);
+
+    let found = router.find("/files/a/b.txt").unwrap();
+    assert_eq!(found.params, [("rest".to_string(), "a/b.txt".to_string())]);
+    let found = router.find("/files").unwrap();
+    assert_eq!(found.params, [("rest".to_string(), String::new())]);
+
+    for path in [
+        "/users//avatar",
+        "/users/42",
+        "/users/42/avatar/x",
+        "/filesx/a",
+    ] {
+        assert!(router.find(path).is_none(), "{path}");
+    }
+}
+
+#[test]
+fn patterns_come_after_exact_paths_and_before_regexes() {
+    let router = router(&[
+        "/users",
+        "/users/me/avatar",
+        "/users/:id/avatar",
+        "/users/:id/:file",
+        "~ avatar$",
+    ]);
+    assert_eq!(
+        route(&router, "/users/me/avatar").unwrap().0,
+        "/users/me/avatar"
+    );
+    assert_eq!(
+        route(&router, "/users/7/avatar").unwrap().0,
+        "/users/:id/avatar"
+    );
+    assert_eq!(
+        route(&router, "/users/7/photo").unwrap().0,
+        "/users/:id/:file"
+    );
+    assert_eq!(route(&router, "/users/7/8/avatar").unwrap().0, "~ avatar$");
+    assert_eq!(route(&router, "/users/7/8/photo").unwrap().0, "/users");
+}
+
+#[test]
+fn invalid_patterns_are_refused() {
+    for key in [
+        "/files/*rest/x",
+        "/users/:/x",
+        "/users/:1d",
+        "/a/:id/:id",
+        "/a/:i-d",
+    ] {
+        let routes = HashMap::from([(key.to_string(), RouteConfig::default())]);
+        assert!(Router::new(&routes).is_err(), "{key}");
+    }
+}
+
+// Route with the given rewrite rules: (pattern, replacement, flag)
+fn rewriting(rules: &[(&str, &str, Option<&str>)]) -> RouteConfig {
+    RouteConfig {
+        rewrites: Some(
+            rules
+                .iter()
+                .map(|(pattern, replacement, flag)| RewriteRule {
+                    pattern: pattern.to_string(),
+                    replacement: replacement.to_string(),
+                    flag: flag.map(str::to_string),
+                })
+                .collect(),
+        ),
+        ..RouteConfig::default()
+    }
+}
+
+// Key of the route a request ends up in, with its rewritten path and query
+fn rewrite(router: &Router, target: &str) -> Result<Option<(String, String)>, String> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (mut path, mut query) = (
+        path.to_string(),
+        Some(query.to_string()).filter(|q| !q.is_empty()),
+    );
+    let found = router.route(&mut path, &mut query)?;
+    let target = match query {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    Ok(found.map(|found| (found.key.to_string(), target)))
+}
+
+#[test]
+fn last_routes_the_rewritten_path_again() {
+    let routes = HashMap::from([
+        (
+            "/blog".to_string(),
+            rewriting(&[(r"^/blog/(\d+)$", "/cgi-bin/blog.py?id=$1", Some("last"))]),
+        ),
+        ("/cgi-bin".to_string(), RouteConfig::default()),
+    ]);
+    let router = Router::new(&routes).unwrap();
+    assert_eq!(
+        rewrite(&router, "/blog/42?lang=fr"),
+        Ok(Some((
+            "/cgi-bin".into(),
+            "/cgi-bin/blog.py?id=42&lang=fr".into()
+        )))
+    );
+    assert_eq!(
+        rewrite(&router, "/blog/new"),
+        Ok(Some(("/blog".into(), "/blog/new".into())))
+    );
+}
+
+#[test]
+fn break_stays_in_the_route() {
+    let routes = HashMap::from([
+        (
+            "/old".to_string(),
+            rewriting(&[
+                ("^/old/(?P<page>.*)$", "/new/${page}?", Some("break")),
+                ("^/new/", "/never", None),
+            ]),
+        ),
+        ("/new".to_string(), RouteConfig::default()),
+    ]);
+    let router = Router::new(&routes).unwrap();
+    let (mut path, mut query) = ("/old/a.html".to_string(), Some("x=1".to_string()));
+    let found = router.route(&mut path, &mut query).unwrap().unwrap();
+    assert_eq!(
+        (found.key, path.as_str(), query),
+        ("/old", "/new/a.html", None)
+    );
+    assert!(!found.own_path);
+}
+
+#[test]
+fn rules_without_flag_chain_then_route_again() {
+    let routes = HashMap::from([
+        (
+            "/a".to_string(),
+            rewriting(&[("^/a$", "/b", None), ("^/b$", "/c", None)]),
+        ),
+        ("/c".to_string(), RouteConfig::default()),
+    ]);
+    let router = Router::new(&routes).unwrap();
+    assert_eq!(rewrite(&router, "/a"), Ok(Some(("/c".into(), "/c".into()))));
+}
+
+#[test]
+fn rewrite_loops_are_detected() {
+    let routes = HashMap::from([
+        ("/a".to_string(), rewriting(&[("^/a$", "/b", Some("last"))])),
+        ("/b".to_string(), rewriting(&[("^/b$", "/a", Some("last"))])),
+        (
+            "/self".to_string(),
+            rewriting(&[("^/self(.*)$", "/self/x$1", None)]),
+        ),
+    ]);
+    let router = Router::new(&routes).unwrap();
+    assert!(rewrite(&router, "/a").is_err());
+    assert!(rewrite(&router, "/self").is_err());
+}
+
+#[test]
+fn invalid_rewrites_are_refused() {
+    for rule in [
+        ("(", "/x", None),
+        ("^/a$", "/b", Some("permanent")),
+        ("^/a$", "b", None),
+    ] {
+        let routes = HashMap::from([("/a".to_string(), rewriting(&[rule]))]);
+        assert!(Router::new(&routes).is_err(), "{rule:?}");
+    }
+}
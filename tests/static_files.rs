@@ -0,0 +1,153 @@
+// Directories are served by their index file once requested with a trailing slash,
+// try_files picks the first existing entry, the last one being the fallback, and
+// error pages come from the root of the server.
+
+mod common;
+
+use std::{collections::HashMap, fs, path::Path};
+
+use localhost::{
+    config::RouteConfig,
+    http_request::HttpRequest,
+    http_response::{ErrorPages, HttpResponse},
+    path_resolver::DocumentRoot,
+};
+
+use common::TempDir;
+
+// A fresh document root: index.html, about.html, docs/index.htm, empty/
+fn sandbox(name: &str) -> TempDir {
+    let root = TempDir::new(&format!("static-{name}"));
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::create_dir_all(root.join("empty")).unwrap();
+    fs::write(root.join("index.html"), "home").unwrap();
+    fs::write(root.join("about.html"), "about").unwrap();
+    fs::write(root.join("docs/index.htm"), "docs").unwrap();
+    root
+}
+
+// Status, Location and body of the response to a GET of `target`
+fn get(root: &Path, route: &RouteConfig, target: &str) -> (u16, Option<String>, String) {
+    let head = format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n");
+    let request = HttpRequest::parse_head(head.as_bytes(), -1, -1).unwrap();
+    let mut response = HttpResponse::serve_files(request, route, &DocumentRoot::root(root), None);
+    let location = response.headers.get("Location").map(str::to_string);
+    let body = match response.status_code {
+        200 => String::from_utf8(response.body.next_chunk().unwrap()).unwrap(),
+        _ => String::new(),
+    };
+    (response.status_code, location, body)
+}
+
+#[test]
+fn directories_are_redirected_then_served_by_their_index() {
+    let root = sandbox("index");
+    let route = RouteConfig {
+        index: Some(vec!["index.html".to_string(), "index.htm".to_string()]),
+        ..RouteConfig::default()
+    };
+    assert_eq!(
+        get(&root, &route, "/docs?page=2"),
+        (301, Some("/docs/?page=2".to_string()), String::new())
+    );
+    assert_eq!(get(&root, &route, "/docs/"), (200, None, "docs".into()));
+    assert_eq!(get(&root, &route, "/"), (200, None, "home".into()));
+    assert_eq!(get(&root, &route, "/empty/").0, 404);
+
+    // Without `index`, only index.html is tried
+    assert_eq!(get(&root, &RouteConfig::default(), "/docs/").0, 404);
+    let listing = RouteConfig {
+        directory_listing: Some(true),
+        ..RouteConfig::default()
+    };
+    assert_eq!(get(&root, &listing, "/empty/").0, 200);
+}
+
+#[test]
+fn try_files_falls_back_to_the_last_entry() {
+    let root = sandbox("try-files");
+    let spa = RouteConfig {
+        try_files: Some(vec![
+            "$uri".to_string(),
+            "$uri.html".to_string(),
+            "/index.html".to_string(),
+        ]),
+        ..RouteConfig::default()
+    };
+    assert_eq!(get(&root, &spa, "/about.html"), (200, None, "about".into()));
+    assert_eq!(get(&root, &spa, "/about"), (200, None, "about".into()));
+    assert_eq!(
+        get(&root, &spa, "/app/users/42"),
+        (200, None, "home".into())
+    );
+    assert_eq!(get(&root, &spa, "/../secret").0, 403);
+
+    let strict = RouteConfig {
+        try_files: Some(vec!["$uri".to_string(), "=403".to_string()]),
+        ..RouteConfig::default()
+    };
+    assert_eq!(get(&root, &strict, "/index.html").0, 200);
+    assert_eq!(get(&root, &strict, "/missing").0, 403);
+}
+
+#[test]
+fn error_pages_are_read_from_the_server_root() {
+    let root = sandbox("error-pages");
+    fs::create_dir_all(root.join("errors")).unwrap();
+    fs::write(root.join("errors/404.html"), "custom 404").unwrap();
+    fs::write(root.join("error.html"), "{{status_code}}: {{message}}").unwrap();
+    let error_pages = |pages: &[(u16, &str)]| {
+        Some(ErrorPages {
+            root: root.to_path_buf(),
+            pages: pages
+                .iter()
+                .map(|&(status, page)| (status, page.to_string()))
+                .collect(),
+        })
+    };
+    let body = |mut response: HttpResponse| {
+        String::from_utf8(response.body.next_chunk().unwrap()).unwrap()
+    };
+
+    assert_eq!(
+        body(HttpResponse::not_found(error_pages(&[(
+            404,
+            "errors/404.html"
+        )]))),
+        "custom 404"
+    );
+    assert_eq!(
+        body(HttpResponse::not_found(error_pages(&[(
+            404,
+            "/errors/404.html"
+        )]))),
+        "custom 404"
+    );
+    // A missing custom page, or one outside the root, gives way to the template
+    assert_eq!(
+        body(HttpResponse::not_found(error_pages(&[(
+            404,
+            "errors/none.html"
+        )]))),
+        "404: Not Found"
+    );
+    assert_eq!(
+        body(HttpResponse::not_found(error_pages(&[(
+            404,
+            "../index.html"
+        )]))),
+        "404: Not Found"
+    );
+
+    // Without a template in the root, the built-in one is used
+    let bare = TempDir::new("static-bare");
+    let pages = Some(ErrorPages {
+        root: bare.to_path_buf(),
+        pages: HashMap::new(),
+    });
+    let page = body(HttpResponse::internal_server_error(pages));
+    assert!(
+        page.contains("500") && page.contains("Internal Server Error"),
+        "{page}"
+    );
+}
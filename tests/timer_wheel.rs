@@ -0,0 +1,38 @@
+// The wheel fires timers at their deadline, never before, and lets the event
+// loop sleep until the earliest one.
+
+use std::time::{Duration, Instant};
+
+use localhost::timer::{TimerWheel, TICK};
+
+#[test]
+fn sleeps_until_the_earliest_deadline() {
+    let mut wheel = TimerWheel::new();
+    let now = Instant::now();
+    assert_eq!(wheel.next_timeout(now), None);
+
+    wheel.schedule(1, 1, now + Duration::from_secs(5));
+    wheel.schedule(2, 2, now + Duration::from_secs(3));
+    let timeout = wheel.next_timeout(now).unwrap();
+    assert!(timeout >= Duration::from_secs(3) && timeout <= Duration::from_secs(3) + TICK);
+
+    // More than a revolution of the wheel away
+    let mut wheel = TimerWheel::new();
+    wheel.schedule(1, 1, now + Duration::from_secs(120));
+    let timeout = wheel.next_timeout(now).unwrap();
+    assert!(timeout >= Duration::from_secs(120), "{timeout:?}");
+}
+
+#[test]
+fn timers_fire_once_due() {
+    let mut wheel = TimerWheel::new();
+    let now = Instant::now();
+    wheel.schedule(1, 7, now + Duration::from_secs(3));
+    wheel.schedule(2, 8, now + Duration::from_secs(60));
+
+    assert!(wheel.expire(now + Duration::from_secs(2)).is_empty());
+    assert_eq!(wheel.expire(now + Duration::from_secs(3) + TICK), [(1, 7)]);
+    assert!(wheel.expire(now + Duration::from_secs(59)).is_empty());
+    assert_eq!(wheel.expire(now + Duration::from_secs(61)), [(2, 8)]);
+    assert!(wheel.is_empty());
+}